@@ -0,0 +1,404 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::ks::{
+    board::Board,
+    cage::Cage,
+    cancel::CancellationToken,
+    chains::{allows_any, apply_eliminations, get_candidate, CandidateSet, LinkGraph},
+    combinations::{get_combinations, get_placeable_values, PossibleValues},
+    error::SolverError,
+    util::popcnt64,
+};
+
+const MAX_ALS_SIZE: usize = 4;
+const MAX_PETALS: usize = 4;
+/// The most almost locked sets tried as the petal for each value of a stem
+const MAX_SETS_PER_PETAL: usize = 8;
+
+/// N cells which must have distinct values, between which only N + 1 values are possible, so that
+/// removing any one of the values locks the rest into the set
+pub struct AlmostLockedSet {
+    cells: Vec<usize>,
+    values: u64,
+    /// The cage whose cells must have distinct values
+    cage: Cage,
+    /// For each value, the candidates which see every cell of the set where the value is possible
+    seen_by: Vec<CandidateSet>,
+}
+
+impl AlmostLockedSet {
+    fn new(cells: Vec<usize>, values: u64, cage: Cage, board: &Board, graph: &LinkGraph) -> Self {
+        let shape = board.shape();
+        let seen_by = (0..=shape.size())
+            .map(|value| {
+                cells
+                    .iter()
+                    .filter(|cell_index| value > 0 && board[**cell_index].allows(value))
+                    .map(|cell_index| {
+                        graph
+                            .get_weak(get_candidate(&shape, *cell_index, value))
+                            .clone()
+                    })
+                    .reduce(|accum, seen| accum.intersection(&seen))
+                    .unwrap_or_else(|| CandidateSet::new(&shape))
+            })
+            .collect();
+        Self {
+            cells,
+            values,
+            cage,
+            seen_by,
+        }
+    }
+
+    fn is_disjoint(&self, other: &Self) -> bool {
+        self.cells
+            .iter()
+            .all(|cell_index| other.cells.binary_search(cell_index).is_err())
+    }
+
+    /// Every cell of other where the value is possible sees every cell of self where it is possible
+    fn sees_all(&self, other: &Self, value: usize, board: &Board) -> bool {
+        let shape = board.shape();
+        other
+            .cells
+            .iter()
+            .filter(|cell_index| board[**cell_index].allows(value))
+            .all(|cell_index| {
+                self.seen_by[value].contains(get_candidate(&shape, *cell_index, value))
+            })
+    }
+
+    /// Values which cannot be in both sets at once
+    fn get_restricted_common_values(&self, other: &Self, board: &Board) -> u64 {
+        if self.is_disjoint(other) {
+            PossibleValues::new(self.values & other.values)
+                .filter(|value| self.sees_all(other, *value, board))
+                .fold(0, |accum, value| accum | (1 << value))
+        } else {
+            0
+        }
+    }
+}
+
+/// Find the almost locked sets among the unsolved cells of each cage with uniqueness, which
+/// includes the houses as well as the killer cages; the values of each cell are those it can take
+/// in one of the remaining combinations of its cage
+pub fn get_almost_locked_sets(
+    board: &Board,
+    cages: &BTreeSet<Cage>,
+    graph: &LinkGraph,
+    cancellation: &CancellationToken,
+) -> Result<Vec<AlmostLockedSet>, SolverError> {
+    fn choose(
+        index: usize,
+        cells: &[(usize, u64)],
+        cage: &Cage,
+        accum: &mut Vec<(usize, u64)>,
+        output: &mut BTreeMap<Vec<usize>, (u64, Cage)>,
+    ) {
+        if !accum.is_empty() {
+            let values = accum
+                .iter()
+                .fold(0, |values, (_, cell_values)| values | cell_values);
+            if popcnt64(values) == accum.len() + 1 {
+                output
+                    .entry(accum.iter().map(|(cell_index, _)| *cell_index).collect())
+                    .or_insert_with(|| (values, cage.clone()));
+            }
+        }
+        if accum.len() < MAX_ALS_SIZE {
+            for i in index..cells.len() {
+                accum.push(cells[i]);
+                choose(i + 1, cells, cage, accum, output);
+                accum.pop();
+            }
+        }
+    }
+
+    let mut sets = BTreeMap::new();
+    for cage in cages.iter().filter(|cage| cage.uniqueness) {
+        cancellation.check()?;
+        let (solved_cells, unsolved_cells) = cage
+            .cells
+            .iter()
+            .partition::<Vec<usize>, _>(|cell_index| board[**cell_index].get_solution().is_some());
+        if unsolved_cells.is_empty() {
+            continue;
+        }
+        let solved_values = solved_cells
+            .iter()
+            .fold(0, |accum, cell_index| accum | board[*cell_index].get_bits());
+        let remaining_sum = solved_cells
+            .iter()
+            .filter_map(|cell_index| board[*cell_index].get_solution())
+            .try_fold(cage.sum, |accum, value| accum.checked_sub(value))
+            .ok_or_else(|| SolverError::contradiction().in_cage(cage))?;
+        let combinations =
+            get_combinations(unsolved_cells.len(), remaining_sum, board.shape().size())
+                .map_err(|error| error.in_cage(cage))?
+                .into_iter()
+                .filter(|combination| combination & solved_values == 0)
+                .collect::<Vec<u64>>();
+        let cell_masks = unsolved_cells
+            .iter()
+            .map(|cell_index| board[*cell_index].get_bits())
+            .collect::<Vec<u64>>();
+        let placeable_values = get_placeable_values(&cell_masks, &combinations);
+        if let Some(position) = placeable_values.iter().position(|values| *values == 0) {
+            return Err(SolverError::contradiction()
+                .at_cell(unsolved_cells[position])
+                .in_cage(cage));
+        }
+        let cells = unsolved_cells
+            .into_iter()
+            .zip(placeable_values)
+            .collect::<Vec<(usize, u64)>>();
+        choose(0, &cells, cage, &mut vec![], &mut sets);
+    }
+    Ok(sets
+        .into_iter()
+        .map(|(cells, (values, cage))| AlmostLockedSet::new(cells, values, cage, board, graph))
+        .collect())
+}
+
+/// Two sets share a value X which cannot be in both, so one of them is locked; any other shared
+/// value Z must then be in one of them; returns true if progress was made, adding the cages of the
+/// sets to cages
+pub fn restrict_by_als_xz(
+    board: &mut Board,
+    sets: &[AlmostLockedSet],
+    cages: &mut BTreeSet<Cage>,
+) -> Result<bool, SolverError> {
+    let mut eliminations = CandidateSet::new(&board.shape());
+    for (i, a) in sets.iter().enumerate() {
+        for b in sets.iter().skip(i + 1) {
+            let restricted_values = a.get_restricted_common_values(b, board);
+            for z in PossibleValues::new(a.values & b.values) {
+                /* Any restricted common value other than Z will do */
+                if restricted_values & !(1 << z) != 0 {
+                    let seen = a.seen_by[z].intersection(&b.seen_by[z]);
+                    if allows_any(board, &seen) {
+                        cages.extend([a.cage.clone(), b.cage.clone()]);
+                        eliminations.union(&seen);
+                    }
+                }
+            }
+        }
+    }
+    apply_eliminations(board, &eliminations)
+}
+
+/// Sets A and B are each linked to a pivot set C by different restricted common values, so one of
+/// A and B is locked; any value Z shared by A and B must be in one of them; returns true if
+/// progress was made, adding the cages of the three sets to cages
+pub fn restrict_by_als_xy_wing(
+    board: &mut Board,
+    sets: &[AlmostLockedSet],
+    cages: &mut BTreeSet<Cage>,
+    cancellation: &CancellationToken,
+) -> Result<bool, SolverError> {
+    let mut eliminations = CandidateSet::new(&board.shape());
+    for c in sets.iter() {
+        cancellation.check()?;
+        let links = sets
+            .iter()
+            .filter_map(|other| {
+                let restricted_values = c.get_restricted_common_values(other, board);
+                (restricted_values != 0).then_some((other, restricted_values))
+            })
+            .collect::<Vec<(&AlmostLockedSet, u64)>>();
+        for (i, (a, a_values)) in links.iter().enumerate() {
+            for (b, b_values) in links.iter().skip(i + 1) {
+                if !a.is_disjoint(b) {
+                    continue;
+                }
+                for x in PossibleValues::new(*a_values) {
+                    for y in PossibleValues::new(b_values & !(1 << x)) {
+                        for z in PossibleValues::new(a.values & b.values & !(1 << x) & !(1 << y)) {
+                            let seen = a.seen_by[z].intersection(&b.seen_by[z]);
+                            if allows_any(board, &seen) {
+                                cages.extend([a.cage.clone(), b.cage.clone(), c.cage.clone()]);
+                                eliminations.union(&seen);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    apply_eliminations(board, &eliminations)
+}
+
+/// Each value of a stem cell is linked to a petal set which becomes locked if the stem takes that
+/// value; any value Z shared by all the petals must be in one of them; returns true if progress was
+/// made, adding the cages of the petals to cages
+pub fn restrict_by_death_blossom(
+    board: &mut Board,
+    sets: &[AlmostLockedSet],
+    cages: &mut BTreeSet<Cage>,
+    cancellation: &CancellationToken,
+) -> Result<bool, SolverError> {
+    /* Returns true if a blossom was completed from the petals chosen so far which eliminates
+     * candidates not already eliminated; each petal is given by the candidates Z which see it */
+    fn choose_petals(
+        index: usize,
+        petals: &[Vec<(&CandidateSet, &Cage)>],
+        accum: CandidateSet,
+        board: &Board,
+        eliminations: &mut CandidateSet,
+        cages: &mut BTreeSet<Cage>,
+        cancellation: &CancellationToken,
+    ) -> Result<bool, SolverError> {
+        /* Each further petal can only leave fewer candidates */
+        if accum.is_subset(eliminations) {
+            return Ok(false);
+        }
+        if index == petals.len() {
+            eliminations.union(&accum);
+            return Ok(true);
+        }
+        cancellation.check()?;
+        let mut completed = false;
+        for (seen_by, cage) in petals[index].iter() {
+            let seen = accum.intersection(seen_by);
+            if allows_any(board, &seen)
+                && choose_petals(
+                    index + 1,
+                    petals,
+                    seen,
+                    board,
+                    eliminations,
+                    cages,
+                    cancellation,
+                )?
+            {
+                cages.insert((*cage).clone());
+                completed = true;
+            }
+        }
+        Ok(completed)
+    }
+
+    let shape = board.shape();
+    let mut eliminations = CandidateSet::new(&shape);
+    for stem in 0..board.len() {
+        let stem_values = board[stem].get_bits();
+        if !(2..=MAX_PETALS).contains(&popcnt64(stem_values)) {
+            continue;
+        }
+        let petals = PossibleValues::new(stem_values)
+            .map(|value| {
+                sets.iter()
+                    .filter(|set| {
+                        set.cells.binary_search(&stem).is_err()
+                            && set.seen_by[value].contains(get_candidate(&shape, stem, value))
+                    })
+                    .collect::<Vec<&AlmostLockedSet>>()
+            })
+            .collect::<Vec<Vec<&AlmostLockedSet>>>();
+        for z in PossibleValues::new(shape.all_values() & !stem_values) {
+            cancellation.check()?;
+            let mut all = CandidateSet::new(&shape);
+            (0..board.len())
+                .for_each(|cell_index| all.insert(get_candidate(&shape, cell_index, z)));
+            let petals_with_z = petals
+                .iter()
+                .map(|petal| {
+                    petal
+                        .iter()
+                        .filter(|set| (set.values >> z) & 1 == 1)
+                        .take(MAX_SETS_PER_PETAL)
+                        .map(|set| (&set.seen_by[z], &set.cage))
+                        .collect()
+                })
+                .collect::<Vec<Vec<(&CandidateSet, &Cage)>>>();
+            choose_petals(
+                0,
+                &petals_with_z,
+                all,
+                board,
+                &mut eliminations,
+                cages,
+                cancellation,
+            )?;
+        }
+    }
+    apply_eliminations(board, &eliminations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ks::puzzle::Puzzle;
+
+    #[test]
+    fn als_xz_from_house() {
+        /* Cells 0 and 1 can only be {1, 2, 3}, and cell 4 can only be {1, 3} */
+        let mut board = Board::default();
+        board[0].restrict_to(0b0110).unwrap();
+        board[1].restrict_to(0b1110).unwrap();
+        board[4].restrict_to(0b1010).unwrap();
+        let cages = Puzzle::get_houses(&board.shape());
+        let graph = LinkGraph::new(&board, &cages, &CancellationToken::default()).unwrap();
+        let sets =
+            get_almost_locked_sets(&board, &cages, &graph, &CancellationToken::default()).unwrap();
+        assert!(restrict_by_als_xz(&mut board, &sets, &mut BTreeSet::new()).unwrap());
+        for (cell_index, cell) in board.iter().enumerate() {
+            let expected = cell_index >= 9 || [1, 4].contains(&cell_index);
+            assert_eq!(cell.allows(3), expected, "{}", cell_index);
+        }
+    }
+
+    #[test]
+    fn als_xz_from_cage() {
+        /* Cells 2 and 12 are only an almost locked set because they share a killer cage */
+        let mut board = Board::default();
+        board[2].restrict_to(0b0110).unwrap();
+        board[12].restrict_to(0b1110).unwrap();
+        board[11].restrict_to(0b1010).unwrap();
+        let mut cages = Puzzle::get_houses(&board.shape());
+        let graph = LinkGraph::new(&board, &cages, &CancellationToken::default()).unwrap();
+        let sets =
+            get_almost_locked_sets(&board, &cages, &graph, &CancellationToken::default()).unwrap();
+        assert!(!restrict_by_als_xz(&mut board.clone(), &sets, &mut BTreeSet::new()).unwrap());
+
+        let cage = Cage::from_parts(vec![2, 3, 12], 12, true);
+        cages.insert(cage.clone());
+        let graph = LinkGraph::new(&board, &cages, &CancellationToken::default()).unwrap();
+        let sets =
+            get_almost_locked_sets(&board, &cages, &graph, &CancellationToken::default()).unwrap();
+        let mut als_cages = BTreeSet::new();
+        assert!(restrict_by_als_xz(&mut board, &sets, &mut als_cages).unwrap());
+        assert!(als_cages.contains(&cage));
+        for (cell_index, cell) in board.iter().enumerate().skip(9).take(9) {
+            let expected = cell_index == 11 || cell_index == 12;
+            assert_eq!(cell.allows(3), expected, "{}", cell_index);
+        }
+    }
+
+    #[test]
+    fn als_from_cage_combinations() {
+        /* Cells 0 and 1 could take any value, but the cage [0, 1, 2] = 8 is either {1, 2, 5} or
+         * {1, 3, 4}, and cell 2 takes the 5 or the 4, which leaves {1, 2, 3} for cells 0 and 1 */
+        let mut board = Board::default();
+        board[2].restrict_to(0b110000).unwrap();
+        let mut cages = Puzzle::get_houses(&board.shape());
+        let graph = LinkGraph::new(&board, &cages, &CancellationToken::default()).unwrap();
+        let has_set = |sets: &[AlmostLockedSet]| {
+            sets.iter()
+                .any(|set| set.cells == [0, 1] && set.values == 0b1110)
+        };
+        assert!(!has_set(
+            &get_almost_locked_sets(&board, &cages, &graph, &CancellationToken::default()).unwrap()
+        ));
+
+        cages.insert(Cage::from_parts(vec![0, 1, 2], 8, true));
+        let graph = LinkGraph::new(&board, &cages, &CancellationToken::default()).unwrap();
+        assert!(has_set(
+            &get_almost_locked_sets(&board, &cages, &graph, &CancellationToken::default()).unwrap()
+        ));
+    }
+}
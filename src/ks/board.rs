@@ -0,0 +1,221 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::{
+    fmt::Display,
+    ops::{Deref, DerefMut},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ks::cell::Cell;
+
+/// The dimensions of a grid, which is divided into boxes of box_rows by box_cols cells and has as
+/// many rows, columns, boxes, and values as there are cells in a box
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "ShapeDimensions")]
+pub struct Shape {
+    pub box_rows: usize,
+    pub box_cols: usize,
+}
+
+/// The dimensions of a grid as read from a file, before they are checked
+#[derive(Deserialize)]
+struct ShapeDimensions {
+    box_rows: usize,
+    box_cols: usize,
+}
+
+impl TryFrom<ShapeDimensions> for Shape {
+    type Error = String;
+
+    fn try_from(dimensions: ShapeDimensions) -> Result<Self, Self::Error> {
+        let ShapeDimensions { box_rows, box_cols } = dimensions;
+        Self::new(box_rows, box_cols)
+            .ok_or_else(|| format!("Invalid box dimensions: {}x{}", box_rows, box_cols))
+    }
+}
+
+impl Shape {
+    /// The largest grid for which the sets of values in a cage can be enumerated quickly
+    pub const MAX_SIZE: usize = 16;
+
+    pub fn new(box_rows: usize, box_cols: usize) -> Option<Self> {
+        (box_rows > 0 && box_cols > 0 && box_rows.checked_mul(box_cols)? <= Self::MAX_SIZE)
+            .then_some(Self { box_rows, box_cols })
+    }
+
+    pub fn is_classic(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The number of rows, columns, boxes, and values
+    pub fn size(&self) -> usize {
+        self.box_rows * self.box_cols
+    }
+
+    pub fn num_cells(&self) -> usize {
+        self.size() * self.size()
+    }
+
+    /// The sum of the values in a row, column, or box
+    pub fn house_sum(&self) -> usize {
+        self.size() * (self.size() + 1) / 2
+    }
+
+    /// The bitmask of every value
+    pub fn all_values(&self) -> u64 {
+        (1 << (self.size() + 1)) - 2
+    }
+
+    pub fn get_row(&self, cell_index: usize) -> usize {
+        cell_index / self.size()
+    }
+
+    pub fn get_col(&self, cell_index: usize) -> usize {
+        cell_index % self.size()
+    }
+
+    /// Boxes are numbered across each band of box_rows rows, then down the bands
+    pub fn get_box(&self, cell_index: usize) -> usize {
+        let boxes_per_band = self.size() / self.box_cols;
+        (self.get_row(cell_index) / self.box_rows) * boxes_per_band
+            + self.get_col(cell_index) / self.box_cols
+    }
+
+    pub fn row_cells(&self, row: usize) -> impl Iterator<Item = usize> {
+        let size = self.size();
+        (0..size).map(move |col| row * size + col)
+    }
+
+    pub fn col_cells(&self, col: usize) -> impl Iterator<Item = usize> {
+        let size = self.size();
+        (0..size).map(move |row| row * size + col)
+    }
+
+    pub fn box_cells(&self, box_index: usize) -> impl Iterator<Item = usize> {
+        let Self { box_rows, box_cols } = *self;
+        let size = self.size();
+        let (band, stack) = (box_index / (size / box_cols), box_index % (size / box_cols));
+        (0..box_rows).flat_map(move |i| {
+            (0..box_cols).map(move |j| (band * box_rows + i) * size + stack * box_cols + j)
+        })
+    }
+
+    /// The cells of each row, then each column, then each box
+    pub fn get_houses(&self) -> Vec<Vec<usize>> {
+        let size = self.size();
+        (0..size)
+            .map(|row| self.row_cells(row).collect())
+            .chain((0..size).map(|col| self.col_cells(col).collect()))
+            .chain((0..size).map(|box_index| self.box_cells(box_index).collect()))
+            .collect()
+    }
+}
+
+impl Default for Shape {
+    fn default() -> Self {
+        Self {
+            box_rows: 3,
+            box_cols: 3,
+        }
+    }
+}
+
+impl Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.box_rows, self.box_cols)
+    }
+}
+
+/// Parse the dimensions of a box, such as 2x3 for a 6x6 grid
+impl FromStr for Shape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_once('x')
+            .and_then(|(box_rows, box_cols)| {
+                Self::new(box_rows.trim().parse().ok()?, box_cols.trim().parse().ok()?)
+            })
+            .ok_or_else(|| format!("Invalid box dimensions: {}", s))
+    }
+}
+
+/// The values still possible in each cell of a grid
+#[derive(Clone, Debug, PartialEq)]
+pub struct Board {
+    shape: Shape,
+    cells: Vec<Cell>,
+}
+
+impl Board {
+    pub fn new(shape: Shape) -> Self {
+        Self {
+            shape,
+            cells: vec![Cell::new(shape.size()); shape.num_cells()],
+        }
+    }
+
+    pub fn shape(&self) -> Shape {
+        self.shape
+    }
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new(Shape::default())
+    }
+}
+
+impl Deref for Board {
+    type Target = [Cell];
+
+    fn deref(&self) -> &Self::Target {
+        &self.cells
+    }
+}
+
+impl DerefMut for Board {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn houses() {
+        let shape = Shape::new(2, 3).unwrap();
+        assert_eq!(shape.size(), 6);
+        assert_eq!(shape.house_sum(), 21);
+        let houses = shape.get_houses();
+        assert_eq!(houses.len(), 18);
+        assert_eq!(houses[12], vec![0, 1, 2, 6, 7, 8]);
+        assert_eq!(houses[13], vec![3, 4, 5, 9, 10, 11]);
+        assert_eq!(houses[14], vec![12, 13, 14, 18, 19, 20]);
+        for (box_index, house) in houses[12..].iter().enumerate() {
+            assert!(house
+                .iter()
+                .all(|cell_index| shape.get_box(*cell_index) == box_index));
+        }
+        assert_eq!(Shape::default().get_box(80), 8);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("3x4".parse::<Shape>(), Ok(Shape::new(3, 4).unwrap()));
+        assert!("4x5".parse::<Shape>().is_err());
+        assert!("9".parse::<Shape>().is_err());
+        let board = Board::new("4x4".parse().unwrap());
+        assert_eq!(board.len(), 256);
+        assert_eq!(board[0].max_possible_value(), 16);
+
+        /* Dimensions read from a file are checked in the same way */
+        let shape = serde_json::from_str::<Shape>(r#"{"box_rows": 2, "box_cols": 3}"#);
+        assert_eq!(shape.unwrap(), Shape::new(2, 3).unwrap());
+        assert!(serde_json::from_str::<Shape>(r#"{"box_rows": 0, "box_cols": 3}"#).is_err());
+        assert!(serde_json::from_str::<Shape>(r#"{"box_rows": 5, "box_cols": 4}"#).is_err());
+    }
+}
@@ -2,8 +2,12 @@
 
 use std::fmt::Display;
 
-use crate::ks::cell::Cell;
-use crate::ks::combinations::{cage_can_have_uniqueness, get_combinations_union, PossibleValues};
+use crate::ks::board::{Board, Shape};
+use crate::ks::combinations::{
+    cage_can_have_uniqueness, cells_share_house, get_combinations, get_combinations_union,
+    get_placeable_values, PossibleValues,
+};
+use crate::ks::error::SolverError;
 use crate::ks::util::popcnt64;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -14,8 +18,14 @@ pub struct Cage {
 }
 
 impl Cage {
-    pub fn new(cells: Vec<usize>, sum: usize, uniqueness: bool) -> Self {
-        let uniqueness = uniqueness || cage_can_have_uniqueness(&cells);
+    /// A cage whose values must be distinct if required, or if its cells all lie in one house
+    pub fn new(shape: &Shape, cells: Vec<usize>, sum: usize, uniqueness: bool) -> Self {
+        let uniqueness = uniqueness || cage_can_have_uniqueness(shape, &cells);
+        Self::from_parts(cells, sum, uniqueness)
+    }
+
+    /// A cage whose values must be distinct only if required, for callers which already know
+    pub fn from_parts(cells: Vec<usize>, sum: usize, uniqueness: bool) -> Self {
         let mut output = Self {
             cells,
             sum,
@@ -33,7 +43,7 @@ impl Cage {
         }
     }
 
-    fn get_degrees_of_freedom(&self, board: &[Cell; 81]) -> usize {
+    fn get_degrees_of_freedom(&self, board: &Board) -> usize {
         self.cells
             .iter()
             .map(|cell_index| board[*cell_index].num_possible_solutions())
@@ -92,22 +102,44 @@ impl Cage {
     }
 
     /// Returns true if progress was made
-    pub fn restrict_by_uniform_combination(&self, board: &mut [Cell; 81]) -> Result<bool, ()> {
+    pub fn restrict_by_uniform_combination(&self, board: &mut Board) -> Result<bool, SolverError> {
         if self.uniqueness {
             let init_degrees_of_freedom = self.get_degrees_of_freedom(board);
-            let combinations_union = get_combinations_union(self.cells.len(), self.sum)?;
-            self.cells
-                .iter()
-                .try_for_each(|cell_index| board[*cell_index].restrict_to(combinations_union))?;
+            let combinations_union =
+                get_combinations_union(self.cells.len(), self.sum, board.shape().size())?;
+            self.cells.iter().try_for_each(|cell_index| {
+                board[*cell_index]
+                    .restrict_to(combinations_union)
+                    .map_err(|error| error.at_cell(*cell_index))
+            })?;
             Ok(self.get_degrees_of_freedom(board) < init_degrees_of_freedom)
         } else {
             Ok(false)
         }
     }
 
-    pub fn check_for_partitions(&self, board: &mut [Cell; 81]) -> Result<Option<(Cage, Cage)>, ()> {
+    /// Get the values which must appear somewhere in the cage, whichever combination is used
+    pub fn get_required_values(&self, board: &Board) -> Result<u64, SolverError> {
         if !self.uniqueness {
-            return Ok(None);
+            return Ok(0);
+        }
+        let available_values = self
+            .cells
+            .iter()
+            .fold(0, |accum, cell_index| accum | board[*cell_index].get_bits());
+        get_combinations(self.cells.len(), self.sum, board.shape().size())?
+            .into_iter()
+            .filter(|combination| combination & !available_values == 0)
+            .reduce(|accum, combination| accum & combination)
+            .ok_or_else(|| SolverError::contradiction().in_cage(self))
+    }
+
+    pub fn check_for_partitions(
+        &self,
+        board: &mut Board,
+    ) -> Result<Option<(Cage, Cage)>, SolverError> {
+        if !self.uniqueness {
+            return self.split_solved_cells(board);
         }
 
         fn fold_combinations(
@@ -125,7 +157,7 @@ impl Cage {
                     })
                     .collect(),
                 choose => (index..(data.len() - choose - 1))
-                    .map(|i| {
+                    .flat_map(|i| {
                         fold_combinations(i + 1, choose - 1, max_value_len, data)
                             .iter()
                             .filter_map(|(folded_key, folded_value)| {
@@ -136,7 +168,6 @@ impl Cage {
                             })
                             .collect::<Vec<(u64, u64)>>()
                     })
-                    .flatten()
                     .collect(),
             }
         }
@@ -163,7 +194,7 @@ impl Cage {
         };
 
         let possible_cells_by_value = {
-            let mut v = (1..=9)
+            let mut v = (1..=board.shape().size())
                 .map(|value| {
                     (
                         value,
@@ -189,17 +220,30 @@ impl Cage {
             assert_eq!(popcnt64(cells), popcnt64(values));
             let new_cage_cells = gather_cell_indices(cells, true);
             for cell in new_cage_cells.iter() {
-                board[*cell].restrict_to(values)?;
+                board[*cell]
+                    .restrict_to(values)
+                    .map_err(|error| error.at_cell(*cell))?;
             }
             let new_cage_sum = PossibleValues::new(values).sum::<usize>();
-            let new_cage = Cage::new(new_cage_cells, new_cage_sum, self.uniqueness);
+            let new_cage = Cage::new(
+                &board.shape(),
+                new_cage_cells,
+                new_cage_sum,
+                self.uniqueness,
+            );
             let remaining_cage_cells = gather_cell_indices(cells, false);
             for cell in remaining_cage_cells.iter() {
-                board[*cell].restrict_to(!values)?;
+                board[*cell]
+                    .restrict_to(!values)
+                    .map_err(|error| error.at_cell(*cell))?;
             }
             let remaining_cage_sum = self.sum - new_cage_sum;
-            let remaining_cage =
-                Cage::new(remaining_cage_cells, remaining_cage_sum, self.uniqueness);
+            let remaining_cage = Cage::new(
+                &board.shape(),
+                remaining_cage_cells,
+                remaining_cage_sum,
+                self.uniqueness,
+            );
             remaining_cage.restrict_by_uniform_combination(board)?;
             Ok(Some((new_cage, remaining_cage)))
         };
@@ -229,23 +273,214 @@ impl Cage {
         Ok(None)
     }
 
+    /// Remove values which are too small or too large for the cage sum to be reached, given the
+    /// smallest and largest possible values of the other cells; returns true if progress was made
+    pub fn restrict_by_bounds(&self, board: &mut Board) -> Result<bool, SolverError> {
+        let (min_sum, max_sum) =
+            self.cells
+                .iter()
+                .fold((0, 0), |(min_sum, max_sum), cell_index| {
+                    (
+                        min_sum + board[*cell_index].min_possible_value(),
+                        max_sum + board[*cell_index].max_possible_value(),
+                    )
+                });
+        if self.sum < min_sum || self.sum > max_sum {
+            return Err(SolverError::contradiction().in_cage(self));
+        }
+        let mut progress = false;
+        for cell_index in self.cells.iter() {
+            let cell = board[*cell_index];
+            let lower_limit = self.sum.saturating_sub(max_sum - cell.max_possible_value());
+            let upper_limit = (self.sum - (min_sum - cell.min_possible_value())).min(62);
+            let range_mask = ((1 << (upper_limit + 1)) - 1) & !((1 << lower_limit) - 1);
+            if cell.get_bits() & !range_mask != 0 {
+                board[*cell_index]
+                    .restrict_to(range_mask)
+                    .map_err(|error| error.at_cell(*cell_index))?;
+                progress = true;
+            }
+        }
+        Ok(progress)
+    }
+
+    /// Split the solved cells off a cage whose values may repeat
+    fn split_solved_cells(&self, board: &Board) -> Result<Option<(Cage, Cage)>, SolverError> {
+        let (solved_cells, unsolved_cells): (Vec<usize>, Vec<usize>) = self
+            .cells
+            .iter()
+            .partition(|cell_index| board[**cell_index].get_solution().is_some());
+        if solved_cells.is_empty() || unsolved_cells.is_empty() {
+            return Ok(None);
+        }
+        let solved_sum = solved_cells
+            .iter()
+            .filter_map(|cell_index| board[*cell_index].get_solution())
+            .sum::<usize>();
+        if solved_sum + unsolved_cells.len() > self.sum {
+            return Err(SolverError::contradiction().in_cage(self));
+        }
+        Ok(Some((
+            Cage::new(&board.shape(), solved_cells, solved_sum, false),
+            Cage::new(&board.shape(), unsolved_cells, self.sum - solved_sum, false),
+        )))
+    }
+
+    /// Search the assignments of a cage whose values may repeat, only requiring distinct values in
+    /// cells which share a row, column, or box; returns true if progress was made
+    fn restrict_by_multiset(&self, board: &mut Board) -> Result<bool, SolverError> {
+        /* Maximum number of search nodes per cell value before assuming it is possible */
+        const SEARCH_LIMIT: usize = 10000;
+
+        fn search(
+            index: usize,
+            remaining_sum: usize,
+            cell_masks: &[u64],
+            conflicts: &[u64],
+            bounds: &[(usize, usize)],
+            assignment: &mut [usize],
+            budget: &mut usize,
+        ) -> Option<bool> {
+            if index == cell_masks.len() {
+                return Some(remaining_sum == 0);
+            }
+            let (min_sum, max_sum) = bounds[index];
+            if remaining_sum < min_sum || remaining_sum > max_sum {
+                return Some(false);
+            }
+            *budget = budget.checked_sub(1)?;
+            let excluded_values = PossibleValues::new(conflicts[index] & ((1 << index) - 1))
+                .fold(0, |accum, other_index| {
+                    accum | (1 << assignment[other_index])
+                });
+            for value in PossibleValues::new(cell_masks[index] & !excluded_values)
+                .take_while(|value| *value <= remaining_sum)
+            {
+                assignment[index] = value;
+                let remaining_sum = remaining_sum - value;
+                if search(
+                    index + 1,
+                    remaining_sum,
+                    cell_masks,
+                    conflicts,
+                    bounds,
+                    assignment,
+                    budget,
+                )? {
+                    return Some(true);
+                }
+            }
+            Some(false)
+        }
+
+        let cell_masks = self
+            .cells
+            .iter()
+            .map(|cell_index| board[*cell_index].get_bits())
+            .collect::<Vec<u64>>();
+        let conflicts = self
+            .cells
+            .iter()
+            .map(|a| {
+                self.cells
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, b)| a != *b && cells_share_house(&board.shape(), *a, **b))
+                    .fold(0, |accum, (i, _)| accum | (1 << i))
+            })
+            .collect::<Vec<u64>>();
+        let mut supported_values = vec![0; self.cells.len()];
+        for (i, mask) in cell_masks.iter().enumerate() {
+            for value in PossibleValues::new(mask & !supported_values[i]) {
+                let mut restricted_masks = cell_masks.clone();
+                restricted_masks[i] = 1 << value;
+                /* Range of sums which the cells from each index onwards can make */
+                let mut bounds = vec![(0, 0); self.cells.len()];
+                let mut accum = (0, 0);
+                for (j, mask) in restricted_masks.iter().enumerate().rev() {
+                    accum.0 += mask.trailing_zeros() as usize;
+                    accum.1 += 63 - mask.leading_zeros() as usize;
+                    bounds[j] = accum;
+                }
+                let mut assignment = vec![0; self.cells.len()];
+                let mut budget = SEARCH_LIMIT;
+                match search(
+                    0,
+                    self.sum,
+                    &restricted_masks,
+                    &conflicts,
+                    &bounds,
+                    &mut assignment,
+                    &mut budget,
+                ) {
+                    Some(true) => assignment
+                        .iter()
+                        .enumerate()
+                        .for_each(|(j, value)| supported_values[j] |= 1 << value),
+                    Some(false) => {}
+                    None => supported_values[i] |= 1 << value,
+                }
+            }
+        }
+        self.cells
+            .iter()
+            .zip(supported_values)
+            .try_for_each(|(cell_index, values)| {
+                board[*cell_index]
+                    .restrict_to(values)
+                    .map_err(|error| error.at_cell(*cell_index))
+            })?;
+        Ok(self
+            .cells
+            .iter()
+            .zip(cell_masks)
+            .any(|(cell_index, mask)| board[*cell_index].get_bits() != mask))
+    }
+
     /// Returns true if progress was made
-    pub fn restrict_by_combination(&self, board: &mut [Cell; 81]) -> Result<bool, ()> {
+    pub fn restrict_by_combination(&self, board: &mut Board) -> Result<bool, SolverError> {
         match self.cells.len() {
             0 => panic!("Invalid condition"),
             1 => Ok(false),
-            2 => {
+            2 if !self.uniqueness => {
                 let get_complement_bits = |mask: u64| {
                     (mask.reverse_bits() >> (64 - self.sum - 1)) & ((1 << self.sum) - 2)
                 };
                 let a_mask = board[self.cells[0]].get_bits();
                 let b_mask = board[self.cells[1]].get_bits();
-                board[self.cells[1]].restrict_to(get_complement_bits(a_mask))?;
-                board[self.cells[0]].restrict_to(get_complement_bits(b_mask))?;
+                board[self.cells[1]]
+                    .restrict_to(get_complement_bits(a_mask))
+                    .map_err(|error| error.at_cell(self.cells[1]))?;
+                board[self.cells[0]]
+                    .restrict_to(get_complement_bits(b_mask))
+                    .map_err(|error| error.at_cell(self.cells[0]))?;
                 Ok((a_mask != board[self.cells[0]].get_bits())
                     || (b_mask != board[self.cells[1]].get_bits()))
             }
-            _ => Ok(false),
+            _ if self.uniqueness => {
+                let cell_masks = self
+                    .cells
+                    .iter()
+                    .map(|cell_index| board[*cell_index].get_bits())
+                    .collect::<Vec<u64>>();
+                let combinations =
+                    get_combinations(self.cells.len(), self.sum, board.shape().size())?;
+                let placeable_values = get_placeable_values(&cell_masks, &combinations);
+                self.cells
+                    .iter()
+                    .zip(placeable_values)
+                    .try_for_each(|(cell_index, values)| {
+                        board[*cell_index]
+                            .restrict_to(values)
+                            .map_err(|error| error.at_cell(*cell_index))
+                    })?;
+                Ok(self
+                    .cells
+                    .iter()
+                    .zip(cell_masks)
+                    .any(|(cell_index, mask)| board[*cell_index].get_bits() != mask))
+            }
+            _ => self.restrict_by_multiset(board),
         }
     }
 }
@@ -255,3 +490,76 @@ impl Display for Cage {
         write!(f, "{:?} = {}", self.cells, self.sum)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infer_uniqueness() {
+        /* Cells 5 and 6 share a row of a 9x9 grid, but not of a 6x6 grid */
+        let shape = Shape::default();
+        assert!(Cage::new(&shape, vec![5, 6], 3, false).uniqueness);
+        assert!(!Cage::new(&shape, vec![5, 15], 3, false).uniqueness);
+        let shape = Shape::new(2, 3).unwrap();
+        assert!(!Cage::new(&shape, vec![5, 6], 3, false).uniqueness);
+        assert!(!Cage::from_parts(vec![5, 6], 3, false).uniqueness);
+    }
+
+    #[test]
+    fn multiset_combination() {
+        /* Cells 0 and 1 share a row but cell 13 shares no house with either */
+        let mut board = Board::default();
+        let cage = Cage::from_parts(vec![0, 1, 13], 4, false);
+        assert!(!cage.uniqueness);
+        assert!(cage.restrict_by_combination(&mut board).unwrap());
+        assert_eq!(
+            board[0].possible_values().collect::<Vec<usize>>(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            board[1].possible_values().collect::<Vec<usize>>(),
+            vec![1, 2]
+        );
+        assert_eq!(board[13].get_solution(), Some(1));
+    }
+
+    #[test]
+    fn bounds() {
+        let mut board = Board::default();
+        let cage = Cage::from_parts(vec![0, 13, 40], 25, false);
+        assert!(cage.restrict_by_bounds(&mut board).unwrap());
+        assert_eq!(
+            board[0].possible_values().collect::<Vec<usize>>(),
+            vec![7, 8, 9]
+        );
+        assert!(!cage.restrict_by_bounds(&mut board).unwrap());
+
+        board[13].restrict_to(1 << 9).unwrap();
+        board[40].restrict_to(1 << 7).unwrap();
+        assert!(cage.restrict_by_bounds(&mut board).unwrap());
+        assert_eq!(board[0].get_solution(), Some(9));
+    }
+
+    #[test]
+    fn multiset_split_solved_cells() {
+        let mut board = Board::default();
+        board[13].restrict_to(1 << 5).unwrap();
+        let cage = Cage::from_parts(vec![0, 1, 13], 12, false);
+        let (solved_cage, remaining_cage) = cage.check_for_partitions(&mut board).unwrap().unwrap();
+        assert_eq!(solved_cage, Cage::new(&board.shape(), vec![13], 5, false));
+        assert_eq!(remaining_cage, Cage::from_parts(vec![0, 1], 7, true));
+    }
+
+    #[test]
+    fn contradiction_cell() {
+        /* Cell 0 can still be 1 or 2, but cell 1 cannot */
+        let mut board = Board::default();
+        board[1].restrict_to(1 << 5).unwrap();
+        let cage = Cage::from_parts(vec![0, 1], 3, true);
+        let error = cage
+            .restrict_by_uniform_combination(&mut board)
+            .unwrap_err();
+        assert_eq!(error.get_location().unwrap().cell_index, Some(1));
+    }
+}
@@ -1,15 +1,23 @@
 // Copyright 2022 by Daniel Winkelman. All rights reserved.
 
 use crate::ks::combinations::PossibleValues;
+use crate::ks::error::SolverError;
 use crate::ks::util::{onehot, popcnt64};
 use std::fmt::Display;
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Cell {
     possible_values: u64,
 }
 
 impl Cell {
+    /// A cell in which any value from 1 to max_value is possible
+    pub fn new(max_value: usize) -> Self {
+        Cell {
+            possible_values: (1 << (max_value + 1)) - 2,
+        }
+    }
+
     pub fn get_solution(&self) -> Option<usize> {
         onehot(self.possible_values)
     }
@@ -19,20 +27,33 @@ impl Cell {
     }
 
     pub fn allows(&self, value: usize) -> bool {
-        if value == 0 || value > 9 {
+        if value == 0 || value > 63 {
             panic!("Value out of range");
         }
         (self.possible_values >> value) & 1 == 1
     }
 
+    /// Get the smallest possible value, or 0 if the cell is empty
+    pub fn min_possible_value(&self) -> usize {
+        match self.possible_values {
+            0 => 0,
+            bits => bits.trailing_zeros() as usize,
+        }
+    }
+
+    /// Get the largest possible value, or 0 if the cell is empty
+    pub fn max_possible_value(&self) -> usize {
+        63usize.saturating_sub(self.possible_values.leading_zeros() as usize)
+    }
+
     pub fn num_possible_solutions(&self) -> usize {
         popcnt64(self.possible_values)
     }
 
-    pub fn restrict_to(&mut self, possible_values: u64) -> Result<(), ()> {
+    pub fn restrict_to(&mut self, possible_values: u64) -> Result<(), SolverError> {
         self.possible_values &= possible_values;
         if self.possible_values == 0 {
-            Err(())
+            Err(SolverError::contradiction())
         } else {
             Ok(())
         }
@@ -45,9 +66,7 @@ impl Cell {
 
 impl Default for Cell {
     fn default() -> Self {
-        Cell {
-            possible_values: (1 << 10) - 2,
-        }
+        Cell::new(9)
     }
 }
 
@@ -73,6 +92,19 @@ mod tests {
         assert!(c.allows(3));
     }
 
+    #[test]
+    fn test_possible_value_range() {
+        let mut c = Cell::default();
+        assert_eq!(c.min_possible_value(), 1);
+        assert_eq!(c.max_possible_value(), 9);
+        c.restrict_to(0b0101000).unwrap();
+        assert_eq!(c.min_possible_value(), 3);
+        assert_eq!(c.max_possible_value(), 5);
+        assert!(c.restrict_to(0).is_err());
+        assert_eq!(c.min_possible_value(), 0);
+        assert_eq!(c.max_possible_value(), 0);
+    }
+
     #[test]
     fn test_possible_values_iterator() {
         let possible_values = PossibleValues::new(0x108f);
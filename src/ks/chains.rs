@@ -0,0 +1,669 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::collections::{btree_map::Entry, BTreeMap, BTreeSet, VecDeque};
+
+use crate::ks::{
+    board::{Board, Shape},
+    cage::Cage,
+    cancel::CancellationToken,
+    combinations::{get_combinations, PossibleValues},
+    error::SolverError,
+};
+
+/// Candidates are numbered by cell, then by value
+pub fn get_candidate(shape: &Shape, cell_index: usize, value: usize) -> usize {
+    cell_index * shape.size() + value - 1
+}
+
+pub fn get_cell_index(shape: &Shape, candidate: usize) -> usize {
+    candidate / shape.size()
+}
+
+pub fn get_value(shape: &Shape, candidate: usize) -> usize {
+    candidate % shape.size() + 1
+}
+
+#[derive(Clone, PartialEq)]
+pub struct CandidateSet(Vec<u64>);
+
+impl CandidateSet {
+    /// An empty set, with room for every candidate of the grid
+    pub fn new(shape: &Shape) -> Self {
+        Self(vec![0; (shape.num_cells() * shape.size()).div_ceil(64)])
+    }
+
+    pub fn insert(&mut self, candidate: usize) {
+        self.0[candidate / 64] |= 1 << (candidate % 64);
+    }
+
+    pub fn contains(&self, candidate: usize) -> bool {
+        (self.0[candidate / 64] >> (candidate % 64)) & 1 == 1
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut output = self.clone();
+        output
+            .0
+            .iter_mut()
+            .zip(other.0.iter())
+            .for_each(|(a, b)| *a &= b);
+        output
+    }
+
+    pub fn union(&mut self, other: &Self) {
+        self.0
+            .iter_mut()
+            .zip(other.0.iter())
+            .for_each(|(a, b)| *a |= b);
+    }
+
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.0.iter().zip(other.0.iter()).all(|(a, b)| a & !b == 0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().enumerate().flat_map(|(word_index, word)| {
+            PossibleValues::new(*word).map(move |bit| word_index * 64 + bit)
+        })
+    }
+}
+
+/// Links between the candidates remaining on the board. Of two strongly linked candidates, at
+/// least one is true; of two weakly linked candidates, at most one is true.
+pub struct LinkGraph {
+    candidates: CandidateSet,
+    strong: Vec<BTreeSet<usize>>,
+    weak: Vec<CandidateSet>,
+    /// The cages with uniqueness, from which the links between cells come
+    cages: Vec<Cage>,
+}
+
+impl LinkGraph {
+    pub fn new(
+        board: &Board,
+        cages: &BTreeSet<Cage>,
+        cancellation: &CancellationToken,
+    ) -> Result<Self, SolverError> {
+        let shape = board.shape();
+        let num_candidates = shape.num_cells() * shape.size();
+        let mut graph = Self {
+            candidates: CandidateSet::new(&shape),
+            strong: vec![BTreeSet::new(); num_candidates],
+            weak: vec![CandidateSet::new(&shape); num_candidates],
+            cages: cages
+                .iter()
+                .filter(|cage| cage.uniqueness)
+                .cloned()
+                .collect(),
+        };
+
+        /* Each cell has exactly one value */
+        for (cell_index, cell) in board.iter().enumerate() {
+            let values = cell.possible_values().collect::<Vec<usize>>();
+            for a in values.iter() {
+                graph
+                    .candidates
+                    .insert(get_candidate(&shape, cell_index, *a));
+                for b in values.iter().filter(|b| *b != a) {
+                    graph.add_weak(
+                        get_candidate(&shape, cell_index, *a),
+                        get_candidate(&shape, cell_index, *b),
+                    );
+                }
+            }
+            if let [a, b] = values[..] {
+                graph.add_strong(
+                    get_candidate(&shape, cell_index, a),
+                    get_candidate(&shape, cell_index, b),
+                );
+            }
+        }
+
+        /* Links created by the combinations remaining in cages with uniqueness */
+        for cage in cages.iter().filter(|cage| cage.uniqueness) {
+            cancellation.check()?;
+            let available_values = cage
+                .cells
+                .iter()
+                .fold(0, |accum, cell_index| accum | board[*cell_index].get_bits());
+            let combinations = get_combinations(cage.cells.len(), cage.sum, shape.size())?
+                .into_iter()
+                .filter(|combination| combination & !available_values == 0)
+                .collect::<Vec<u64>>();
+            if combinations.is_empty() {
+                return Err(SolverError::contradiction().in_cage(cage));
+            }
+            let positions = (0..=shape.size())
+                .map(|value| {
+                    cage.cells
+                        .iter()
+                        .filter(|cell_index| value > 0 && board[**cell_index].allows(value))
+                        .cloned()
+                        .collect::<Vec<usize>>()
+                })
+                .collect::<Vec<Vec<usize>>>();
+            for a in PossibleValues::new(available_values) {
+                for b in PossibleValues::new(available_values) {
+                    let pair = (1 << a) | (1 << b);
+                    let always_one = combinations
+                        .iter()
+                        .all(|combination| combination & pair != 0);
+                    let never_both = a == b
+                        || combinations
+                            .iter()
+                            .all(|combination| combination & pair != pair);
+                    if never_both {
+                        for x in positions[a].iter() {
+                            for y in positions[b].iter().filter(|y| *y != x) {
+                                graph.add_weak(
+                                    get_candidate(&shape, *x, a),
+                                    get_candidate(&shape, *y, b),
+                                );
+                            }
+                        }
+                    }
+                    if always_one {
+                        match (&positions[a][..], &positions[b][..]) {
+                            ([x, y], _) if a == b => graph.add_strong(
+                                get_candidate(&shape, *x, a),
+                                get_candidate(&shape, *y, a),
+                            ),
+                            ([x], [y]) if a != b => graph.add_strong(
+                                get_candidate(&shape, *x, a),
+                                get_candidate(&shape, *y, b),
+                            ),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        Ok(graph)
+    }
+
+    fn add_strong(&mut self, a: usize, b: usize) {
+        self.strong[a].insert(b);
+        self.strong[b].insert(a);
+    }
+
+    fn add_weak(&mut self, a: usize, b: usize) {
+        self.weak[a].insert(b);
+        self.weak[b].insert(a);
+    }
+
+    pub fn get_weak(&self, candidate: usize) -> &CandidateSet {
+        &self.weak[candidate]
+    }
+
+    pub fn is_weak(&self, a: usize, b: usize) -> bool {
+        self.weak[a].contains(b)
+    }
+
+    /// The smallest cage with uniqueness containing the cells of both candidates, which is where a
+    /// link between candidates in different cells comes from
+    fn get_linking_cage(&self, shape: &Shape, a: usize, b: usize) -> Option<&Cage> {
+        let (a, b) = (get_cell_index(shape, a), get_cell_index(shape, b));
+        if a == b {
+            return None;
+        }
+        self.cages
+            .iter()
+            .filter(|cage| {
+                cage.cells.binary_search(&a).is_ok() && cage.cells.binary_search(&b).is_ok()
+            })
+            .min_by_key(|cage| cage.cells.len())
+    }
+
+    /// Add the cages linking each pair of candidates
+    fn insert_linking_cages(
+        &self,
+        shape: &Shape,
+        links: &[(usize, usize)],
+        cages: &mut BTreeSet<Cage>,
+    ) {
+        for (a, b) in links.iter() {
+            if let Some(cage) = self.get_linking_cage(shape, *a, *b) {
+                cages.insert(cage.clone());
+            }
+        }
+    }
+
+    /// Candidates which cannot be true if either a or b is true
+    fn get_common_weak(&self, a: usize, b: usize) -> CandidateSet {
+        self.weak[a].intersection(&self.weak[b])
+    }
+
+    fn get_bivalue_cells(&self, board: &Board) -> Vec<(usize, usize, usize)> {
+        (0..board.len())
+            .filter_map(|cell_index| {
+                match board[cell_index].possible_values().collect::<Vec<usize>>()[..] {
+                    [a, b] => Some((cell_index, a, b)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Whether any of the candidates is still possible on the board
+pub fn allows_any(board: &Board, candidates: &CandidateSet) -> bool {
+    let shape = board.shape();
+    candidates.iter().any(|candidate| {
+        board[get_cell_index(&shape, candidate)].allows(get_value(&shape, candidate))
+    })
+}
+
+pub fn apply_eliminations(
+    board: &mut Board,
+    eliminations: &CandidateSet,
+) -> Result<bool, SolverError> {
+    let shape = board.shape();
+    let mut progress = false;
+    for candidate in eliminations.iter() {
+        let cell_index = get_cell_index(&shape, candidate);
+        let cell = &mut board[cell_index];
+        if cell.allows(get_value(&shape, candidate)) {
+            cell.restrict_to(!(1 << get_value(&shape, candidate)))
+                .map_err(|error| error.at_cell(cell_index))?;
+            progress = true;
+        }
+    }
+    Ok(progress)
+}
+
+/// A bivalue pivot XY sees bivalue pincers XZ and YZ, so one of the pincers must be Z; returns
+/// true if progress was made, adding the cages linking the pivot to the pincers to cages
+pub fn restrict_by_xy_wing(
+    board: &mut Board,
+    graph: &LinkGraph,
+    cages: &mut BTreeSet<Cage>,
+) -> Result<bool, SolverError> {
+    let shape = board.shape();
+    let bivalue_cells = graph.get_bivalue_cells(board);
+    let mut eliminations = CandidateSet::new(&shape);
+    for (pivot, x, y) in bivalue_cells.iter() {
+        for (pivot_value, other_value) in [(*x, *y), (*y, *x)] {
+            for (a, a0, a1) in bivalue_cells.iter().filter(|(a, _, _)| a != pivot) {
+                let z = match (*a0 == pivot_value, *a1 == pivot_value) {
+                    (true, false) => *a1,
+                    (false, true) => *a0,
+                    _ => continue,
+                };
+                if z == other_value
+                    || !graph.is_weak(
+                        get_candidate(&shape, *pivot, pivot_value),
+                        get_candidate(&shape, *a, pivot_value),
+                    )
+                {
+                    continue;
+                }
+                for (b, _, _) in bivalue_cells.iter().filter(|(b, b0, b1)| {
+                    b != pivot
+                        && b != a
+                        && ((*b0 == other_value && *b1 == z) || (*b0 == z && *b1 == other_value))
+                }) {
+                    if graph.is_weak(
+                        get_candidate(&shape, *pivot, other_value),
+                        get_candidate(&shape, *b, other_value),
+                    ) {
+                        let wing_eliminations = graph.get_common_weak(
+                            get_candidate(&shape, *a, z),
+                            get_candidate(&shape, *b, z),
+                        );
+                        if allows_any(board, &wing_eliminations) {
+                            graph.insert_linking_cages(
+                                &shape,
+                                &[
+                                    (
+                                        get_candidate(&shape, *pivot, pivot_value),
+                                        get_candidate(&shape, *a, pivot_value),
+                                    ),
+                                    (
+                                        get_candidate(&shape, *pivot, other_value),
+                                        get_candidate(&shape, *b, other_value),
+                                    ),
+                                ],
+                                cages,
+                            );
+                            eliminations.union(&wing_eliminations);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    apply_eliminations(board, &eliminations)
+}
+
+/// A trivalue pivot XYZ sees bivalue pincers XZ and YZ, so one of the three must be Z; returns
+/// true if progress was made, adding the cages linking the pivot to the pincers to cages
+pub fn restrict_by_xyz_wing(
+    board: &mut Board,
+    graph: &LinkGraph,
+    cages: &mut BTreeSet<Cage>,
+) -> Result<bool, SolverError> {
+    let shape = board.shape();
+    let bivalue_cells = graph.get_bivalue_cells(board);
+    let mut eliminations = CandidateSet::new(&shape);
+    for pivot in
+        (0..board.len()).filter(|cell_index| board[*cell_index].num_possible_solutions() == 3)
+    {
+        let values = board[pivot].possible_values().collect::<Vec<usize>>();
+        for z in values.iter() {
+            let pincers = |x: usize| {
+                bivalue_cells
+                    .iter()
+                    .filter(move |(a, a0, a1)| {
+                        ((*a0 == x && a1 == z) || (a0 == z && *a1 == x))
+                            && graph.is_weak(
+                                get_candidate(&shape, pivot, x),
+                                get_candidate(&shape, *a, x),
+                            )
+                    })
+                    .map(|(a, _, _)| *a)
+            };
+            let (x, y) = match values
+                .iter()
+                .filter(|value| *value != z)
+                .collect::<Vec<_>>()[..]
+            {
+                [x, y] => (*x, *y),
+                _ => panic!("Invalid condition"),
+            };
+            for a in pincers(x) {
+                for b in pincers(y) {
+                    let wing_eliminations = graph
+                        .get_common_weak(get_candidate(&shape, a, *z), get_candidate(&shape, b, *z))
+                        .intersection(&graph.weak[get_candidate(&shape, pivot, *z)]);
+                    if allows_any(board, &wing_eliminations) {
+                        graph.insert_linking_cages(
+                            &shape,
+                            &[
+                                (get_candidate(&shape, pivot, x), get_candidate(&shape, a, x)),
+                                (get_candidate(&shape, pivot, y), get_candidate(&shape, b, y)),
+                            ],
+                            cages,
+                        );
+                        eliminations.union(&wing_eliminations);
+                    }
+                }
+            }
+        }
+    }
+    apply_eliminations(board, &eliminations)
+}
+
+/// Colour the chains of conjugate pairs of each value; a colour which sees itself is false, and a
+/// candidate which sees both colours is false; returns true if progress was made, adding the cages
+/// of the conjugate pairs to cages
+pub fn restrict_by_simple_colouring(
+    board: &mut Board,
+    graph: &LinkGraph,
+    cages: &mut BTreeSet<Cage>,
+) -> Result<bool, SolverError> {
+    let shape = board.shape();
+    let mut eliminations = CandidateSet::new(&shape);
+    let mut coloured = CandidateSet::new(&shape);
+    for start in graph.candidates.iter() {
+        if coloured.contains(start) {
+            continue;
+        }
+        /* Colour the component of conjugate pairs containing the start */
+        let mut colours = [CandidateSet::new(&shape), CandidateSet::new(&shape)];
+        let mut queue = VecDeque::from([(start, 0)]);
+        let mut links = vec![];
+        colours[0].insert(start);
+        coloured.insert(start);
+        while let Some((candidate, colour)) = queue.pop_front() {
+            for other in graph.strong[candidate].iter().filter(|other| {
+                get_value(&shape, **other) == get_value(&shape, start)
+                    && graph.is_weak(candidate, **other)
+            }) {
+                if colours[colour].contains(*other) {
+                    /* Exactly one of each conjugate pair is true */
+                    return Err(
+                        SolverError::contradiction().at_cell(get_cell_index(&shape, *other))
+                    );
+                } else if !colours[1 - colour].contains(*other) {
+                    links.push((candidate, *other));
+                    colours[1 - colour].insert(*other);
+                    coloured.insert(*other);
+                    queue.push_back((*other, 1 - colour));
+                }
+            }
+        }
+
+        /* One colour is entirely true and the other is entirely false */
+        let seen_by_colours = colours.each_ref().map(|colour| {
+            colour
+                .iter()
+                .fold(CandidateSet::new(&shape), |mut accum, candidate| {
+                    accum.union(&graph.weak[candidate]);
+                    accum
+                })
+        });
+        let mut colour_eliminations = CandidateSet::new(&shape);
+        for (colour, seen_by_colour) in colours.iter().zip(seen_by_colours.iter()) {
+            if colour
+                .iter()
+                .any(|candidate| seen_by_colour.contains(candidate))
+            {
+                colour_eliminations.union(colour);
+            }
+        }
+        seen_by_colours[0]
+            .intersection(&seen_by_colours[1])
+            .iter()
+            .filter(|candidate| get_value(&shape, *candidate) == get_value(&shape, start))
+            .for_each(|candidate| colour_eliminations.insert(candidate));
+        if allows_any(board, &colour_eliminations) {
+            graph.insert_linking_cages(&shape, &links, cages);
+            eliminations.union(&colour_eliminations);
+        }
+    }
+    apply_eliminations(board, &eliminations)
+}
+
+/// Follow alternating inference chains from every candidate: if the chain starts with a strong
+/// link from A and ends with a strong link to B, then A or B is true, so anything which sees both
+/// is false; if the chain returns to A, then A is true. The cages linking the candidates of each
+/// chain which made progress are added to cages.
+fn restrict_by_chains(
+    board: &mut Board,
+    graph: &LinkGraph,
+    single_value: bool,
+    cages: &mut BTreeSet<Cage>,
+) -> Result<bool, SolverError> {
+    let shape = board.shape();
+    let mut eliminations = CandidateSet::new(&shape);
+    let mut placements = vec![];
+    for start in graph.candidates.iter() {
+        let follows = |candidate: &usize| {
+            !single_value || get_value(&shape, *candidate) == get_value(&shape, start)
+        };
+        /* Candidates which are true or false if the start is false, and the candidate each was
+         * reached from */
+        let mut on = BTreeMap::new();
+        let mut off = BTreeMap::from([(start, start)]);
+        let mut queue = VecDeque::from([(start, false)]);
+        while let Some((candidate, is_on)) = queue.pop_front() {
+            if is_on {
+                for other in graph.weak[candidate].iter().filter(follows) {
+                    if let Entry::Vacant(entry) = off.entry(other) {
+                        entry.insert(candidate);
+                        queue.push_back((other, false));
+                    }
+                }
+            } else {
+                for other in graph.strong[candidate]
+                    .iter()
+                    .filter(|other| follows(other))
+                {
+                    if let Entry::Vacant(entry) = on.entry(*other) {
+                        entry.insert(candidate);
+                        queue.push_back((*other, true));
+                    }
+                }
+            }
+        }
+        let get_links = |end: usize| {
+            let mut links = vec![];
+            let (mut candidate, mut is_on) = (end, true);
+            while is_on || candidate != start {
+                let previous = if is_on {
+                    on[&candidate]
+                } else {
+                    off[&candidate]
+                };
+                links.push((previous, candidate));
+                (candidate, is_on) = (previous, !is_on);
+            }
+            links
+        };
+        if on.contains_key(&start)
+            && board[get_cell_index(&shape, start)]
+                .get_solution()
+                .is_none()
+        {
+            graph.insert_linking_cages(&shape, &get_links(start), cages);
+            placements.push(start);
+        }
+        for end in on.keys().filter(|end| **end != start) {
+            let mut chain_eliminations = CandidateSet::new(&shape);
+            graph
+                .get_common_weak(start, *end)
+                .iter()
+                .filter(follows)
+                .for_each(|candidate| chain_eliminations.insert(candidate));
+            if allows_any(board, &chain_eliminations) {
+                graph.insert_linking_cages(&shape, &get_links(*end), cages);
+                eliminations.union(&chain_eliminations);
+            }
+        }
+    }
+    let mut progress = apply_eliminations(board, &eliminations)?;
+    for candidate in placements {
+        let cell_index = get_cell_index(&shape, candidate);
+        let cell = &mut board[cell_index];
+        if cell.get_solution().is_none() {
+            cell.restrict_to(1 << get_value(&shape, candidate))
+                .map_err(|error| error.at_cell(cell_index))?;
+            progress = true;
+        }
+    }
+    Ok(progress)
+}
+
+/// Alternating inference chains restricted to a single value; returns true if progress was made
+pub fn restrict_by_x_chain(
+    board: &mut Board,
+    graph: &LinkGraph,
+    cages: &mut BTreeSet<Cage>,
+) -> Result<bool, SolverError> {
+    restrict_by_chains(board, graph, true, cages)
+}
+
+/// Alternating inference chains over all candidates, including links created by cage
+/// combinations; returns true if progress was made
+pub fn restrict_by_aic(
+    board: &mut Board,
+    graph: &LinkGraph,
+    cages: &mut BTreeSet<Cage>,
+) -> Result<bool, SolverError> {
+    restrict_by_chains(board, graph, false, cages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ks::{cell::Cell, puzzle::Puzzle};
+
+    fn get_values(cell: &Cell) -> Vec<usize> {
+        cell.possible_values().collect()
+    }
+
+    #[test]
+    fn xy_wing() {
+        let mut board = Board::default();
+        board[0].restrict_to(0b0110).unwrap();
+        board[4].restrict_to(0b1010).unwrap();
+        board[18].restrict_to(0b1100).unwrap();
+        let graph = LinkGraph::new(
+            &board,
+            &Puzzle::get_houses(&board.shape()),
+            &CancellationToken::default(),
+        )
+        .unwrap();
+        assert!(restrict_by_xy_wing(&mut board, &graph, &mut BTreeSet::new()).unwrap());
+        for (cell_index, cell) in board.iter().enumerate() {
+            let expected = ![0, 1, 2, 21, 22, 23].contains(&cell_index);
+            assert_eq!(cell.allows(3), expected, "{}", cell_index);
+        }
+    }
+
+    #[test]
+    fn xyz_wing() {
+        let mut board = Board::default();
+        board[0].restrict_to(0b1110).unwrap();
+        board[1].restrict_to(0b1010).unwrap();
+        board[9].restrict_to(0b1100).unwrap();
+        let graph = LinkGraph::new(
+            &board,
+            &Puzzle::get_houses(&board.shape()),
+            &CancellationToken::default(),
+        )
+        .unwrap();
+        assert!(!restrict_by_xy_wing(&mut board, &graph, &mut BTreeSet::new()).unwrap());
+        assert!(restrict_by_xyz_wing(&mut board, &graph, &mut BTreeSet::new()).unwrap());
+        for (cell_index, cell) in board.iter().enumerate() {
+            let expected = ![2, 10, 11, 18, 19, 20].contains(&cell_index);
+            assert_eq!(cell.allows(3), expected, "{}", cell_index);
+        }
+    }
+
+    #[test]
+    fn simple_colouring() {
+        /* Conjugate pairs of 1 in box 0, row 1, and column 4 */
+        let mut board = Board::default();
+        let exclusions = (0..3)
+            .flat_map(|row| (0..3).map(move |col| row * 9 + col))
+            .filter(|cell_index| *cell_index != 0 && *cell_index != 10)
+            .chain((9..18).filter(|cell_index| *cell_index != 10 && *cell_index != 13))
+            .chain(
+                (0..9)
+                    .map(|row| row * 9 + 4)
+                    .filter(|c| *c != 13 && *c != 49),
+            );
+        for cell_index in exclusions {
+            board[cell_index].restrict_to(!(1 << 1)).unwrap();
+        }
+        let graph = LinkGraph::new(
+            &board,
+            &Puzzle::get_houses(&board.shape()),
+            &CancellationToken::default(),
+        )
+        .unwrap();
+        assert!(restrict_by_simple_colouring(&mut board, &graph, &mut BTreeSet::new()).unwrap());
+        assert!(!board[45].allows(1));
+        assert!(board[46].allows(1));
+    }
+
+    #[test]
+    fn cage_strong_link() {
+        /* The cage can only be {1, 9} or {2, 8}, so cell 1 is 8 or 9 */
+        let mut board = Board::default();
+        board[0].restrict_to(0b0110).unwrap();
+        board[1]
+            .restrict_to((1 << 5) | (1 << 8) | (1 << 9))
+            .unwrap();
+        board[10].restrict_to((1 << 5) | (1 << 9)).unwrap();
+        let mut cages = Puzzle::get_houses(&board.shape());
+        cages.insert(Cage::from_parts(vec![0, 1], 10, true));
+        let graph = LinkGraph::new(&board, &cages, &CancellationToken::default()).unwrap();
+        assert!(!restrict_by_xy_wing(&mut board, &graph, &mut BTreeSet::new()).unwrap());
+        let mut chain_cages = BTreeSet::new();
+        assert!(restrict_by_aic(&mut board, &graph, &mut chain_cages).unwrap());
+        assert_eq!(get_values(&board[1]), vec![8, 9]);
+        assert!(chain_cages.contains(&Cage::from_parts(vec![0, 1], 10, true)));
+    }
+}
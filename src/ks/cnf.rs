@@ -0,0 +1,294 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::collections::BTreeSet;
+
+use crate::ks::{
+    board::{Board, Shape},
+    cage::Cage,
+    combinations::{cage_can_have_uniqueness, get_combinations, PossibleValues},
+    error::SolverError,
+};
+
+/// Variables 1 to the number of cells times the number of values are true if a cell has a value
+fn get_variable(shape: &Shape, cell_index: usize, value: usize) -> i32 {
+    (cell_index * shape.size() + value) as i32
+}
+
+/// Boolean formula in conjunctive normal form
+pub struct Cnf {
+    pub num_variables: usize,
+    pub clauses: Vec<Vec<i32>>,
+}
+
+impl Cnf {
+    /// Encode the board and the cages with uniqueness. Each cell has exactly one value and each
+    /// value appears exactly once in each house. Each cage has an auxiliary variable for each of
+    /// its combinations, at least one of which is true; a true combination forbids the values
+    /// outside of it and requires the values inside of it, which cannot repeat within the cage.
+    pub fn from_board(board: &Board, cages: &BTreeSet<Cage>) -> Result<Self, SolverError> {
+        let shape = board.shape();
+        let size = shape.size();
+        let mut output = Self {
+            num_variables: shape.num_cells() * size,
+            clauses: vec![],
+        };
+        let at_most_one = |variables: &[i32], clauses: &mut Vec<Vec<i32>>| {
+            for (i, a) in variables.iter().enumerate() {
+                for b in variables.iter().skip(i + 1) {
+                    clauses.push(vec![-a, -b]);
+                }
+            }
+        };
+
+        for (cell_index, cell) in board.iter().enumerate() {
+            let variables = (1..=size)
+                .map(|value| get_variable(&shape, cell_index, value))
+                .collect::<Vec<i32>>();
+            output.clauses.push(variables.clone());
+            at_most_one(&variables, &mut output.clauses);
+            for value in PossibleValues::new(shape.all_values() & !cell.get_bits()) {
+                output
+                    .clauses
+                    .push(vec![-get_variable(&shape, cell_index, value)]);
+            }
+        }
+
+        for house in shape.get_houses().iter() {
+            for value in 1..=size {
+                let variables = house
+                    .iter()
+                    .map(|cell_index| get_variable(&shape, *cell_index, value))
+                    .collect::<Vec<i32>>();
+                output.clauses.push(variables.clone());
+                at_most_one(&variables, &mut output.clauses);
+            }
+        }
+
+        /* The houses are already encoded */
+        for cage in cages.iter().filter(|cage| {
+            cage.uniqueness
+                && !(cage.cells.len() == size && cage_can_have_uniqueness(&shape, &cage.cells))
+        }) {
+            for value in 1..=size {
+                let variables = cage
+                    .cells
+                    .iter()
+                    .map(|cell_index| get_variable(&shape, *cell_index, value))
+                    .collect::<Vec<i32>>();
+                at_most_one(&variables, &mut output.clauses);
+            }
+            let mut combination_variables = vec![];
+            for combination in get_combinations(cage.cells.len(), cage.sum, size)? {
+                output.num_variables += 1;
+                let combination_variable = output.num_variables as i32;
+                combination_variables.push(combination_variable);
+                for value in 1..=size {
+                    if (combination >> value) & 1 == 1 {
+                        output.clauses.push(
+                            [-combination_variable]
+                                .into_iter()
+                                .chain(
+                                    cage.cells
+                                        .iter()
+                                        .map(|cell_index| get_variable(&shape, *cell_index, value)),
+                                )
+                                .collect(),
+                        );
+                    } else {
+                        for cell_index in cage.cells.iter() {
+                            output.clauses.push(vec![
+                                -combination_variable,
+                                -get_variable(&shape, *cell_index, value),
+                            ]);
+                        }
+                    }
+                }
+            }
+            output.clauses.push(combination_variables);
+        }
+        Ok(output)
+    }
+
+    pub fn to_dimacs(&self) -> String {
+        let mut output = format!("p cnf {} {}\n", self.num_variables, self.clauses.len());
+        for clause in self.clauses.iter() {
+            for literal in clause.iter() {
+                output += &format!("{} ", literal);
+            }
+            output += "0\n";
+        }
+        output
+    }
+}
+
+/// Read a model in the output format of a SAT solver, where the literals are on lines starting
+/// with "v", and restrict each cell of the board to its true value
+pub fn apply_model(board: &mut Board, model: &str) -> Result<(), SolverError> {
+    if model.lines().any(|line| line.trim() == "s UNSATISFIABLE") {
+        return Err(SolverError::InvalidModel);
+    }
+    let size = board.shape().size();
+    let mut values = vec![0; board.len()];
+    for line in model.lines().filter(|line| line.starts_with('v')) {
+        for literal in line[1..].split_whitespace() {
+            let literal = literal
+                .parse::<i32>()
+                .map_err(|_| SolverError::InvalidModel)?;
+            if (1..=(board.len() * size) as i32).contains(&literal) {
+                let cell_index = (literal as usize - 1) / size;
+                if values[cell_index] != 0 {
+                    return Err(SolverError::InvalidModel);
+                }
+                values[cell_index] = (literal as usize - 1) % size + 1;
+            }
+        }
+    }
+    board
+        .iter_mut()
+        .zip(values.iter())
+        .enumerate()
+        .try_for_each(|(cell_index, (cell, value))| match value {
+            0 => Err(SolverError::InvalidModel),
+            _ => cell
+                .restrict_to(1 << value)
+                .map_err(|error| error.at_cell(cell_index)),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ks::{io::SerializablePuzzle, puzzle::Puzzle};
+
+    /// Tiny DPLL solver with unit propagation; assignments are indexed by variable
+    fn dpll(clauses: &[Vec<i32>], assignment: &mut Vec<Option<bool>>) -> bool {
+        let is_true = |literal: i32, assignment: &Vec<Option<bool>>| {
+            assignment[literal.unsigned_abs() as usize].map(|value| value == (literal > 0))
+        };
+        let mut trail = vec![];
+        let satisfiable = loop {
+            let mut unit = None;
+            let mut branch = None;
+            let mut conflict = false;
+            for clause in clauses.iter() {
+                if clause
+                    .iter()
+                    .any(|literal| is_true(*literal, assignment) == Some(true))
+                {
+                    continue;
+                }
+                let unassigned = clause
+                    .iter()
+                    .filter(|literal| is_true(**literal, assignment).is_none())
+                    .collect::<Vec<&i32>>();
+                match unassigned[..] {
+                    [] => {
+                        conflict = true;
+                        break;
+                    }
+                    [literal] => unit = Some(*literal),
+                    _ => branch = branch.or(Some(*unassigned[0])),
+                }
+            }
+            if conflict {
+                break false;
+            }
+            match (unit, branch) {
+                (Some(literal), _) => {
+                    assignment[literal.unsigned_abs() as usize] = Some(literal > 0);
+                    trail.push(literal.unsigned_abs() as usize);
+                }
+                (None, Some(literal)) => {
+                    let variable = literal.unsigned_abs() as usize;
+                    for value in [literal > 0, literal < 0] {
+                        assignment[variable] = Some(value);
+                        if dpll(clauses, assignment) {
+                            return true;
+                        }
+                    }
+                    assignment[variable] = None;
+                    break false;
+                }
+                (None, None) => break true,
+            }
+        };
+        if !satisfiable {
+            trail
+                .into_iter()
+                .for_each(|variable| assignment[variable] = None);
+        }
+        satisfiable
+    }
+
+    /// Solve a formula in DIMACS CNF format, giving the model in the output format of a SAT solver
+    fn solve_dimacs(dimacs: &str) -> Option<String> {
+        let mut num_variables = 0;
+        let mut clauses = vec![];
+        for line in dimacs.lines() {
+            let tokens = line.split_whitespace().collect::<Vec<&str>>();
+            match tokens[..] {
+                ["p", "cnf", variables, _] => num_variables = variables.parse().unwrap(),
+                _ => clauses.push(
+                    tokens
+                        .iter()
+                        .map(|token| token.parse::<i32>().unwrap())
+                        .take_while(|literal| *literal != 0)
+                        .collect::<Vec<i32>>(),
+                ),
+            }
+        }
+        let mut assignment = vec![None; num_variables + 1];
+        dpll(&clauses, &mut assignment).then(|| {
+            let literals = (1..=num_variables)
+                .map(|variable| match assignment[variable] {
+                    Some(true) => format!("{}", variable),
+                    _ => format!("-{}", variable),
+                })
+                .collect::<Vec<String>>();
+            format!("s SATISFIABLE\nv {} 0\n", literals.join(" "))
+        })
+    }
+
+    #[test]
+    fn dimacs_format() {
+        let cnf = Cnf {
+            num_variables: 3,
+            clauses: vec![vec![1, -3], vec![2, 3, -1]],
+        };
+        assert_eq!(cnf.to_dimacs(), "p cnf 3 2\n1 -3 0\n2 3 -1 0\n");
+    }
+
+    #[test]
+    fn model_round_trip() {
+        let serialized_puzzle =
+            SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap();
+        let cell_values = serialized_puzzle.cell_values.clone();
+        let puzzle = Puzzle::from_serializable(serialized_puzzle).unwrap();
+
+        /* Give away the top half of the board to keep the search small */
+        let mut partial_puzzle = puzzle.clone();
+        for (cell, value) in partial_puzzle
+            .board
+            .iter_mut()
+            .zip(cell_values.iter())
+            .take(36)
+        {
+            cell.restrict_to(1 << value).unwrap();
+        }
+        let model = solve_dimacs(&partial_puzzle.to_dimacs().unwrap()).unwrap();
+        let solution = partial_puzzle.apply_sat_model(&model).unwrap();
+        for (cell, value) in solution.board.iter().zip(cell_values.iter()) {
+            assert_eq!(cell.get_solution(), Some(*value));
+        }
+
+        /* A wrong value in the top half makes the formula unsatisfiable */
+        let mut partial_puzzle = puzzle;
+        for cell_index in 0..36 {
+            partial_puzzle.board[cell_index]
+                .restrict_to(1 << cell_values[(cell_index + 1) % 36])
+                .unwrap();
+        }
+        assert!(solve_dimacs(&partial_puzzle.to_dimacs().unwrap()).is_none());
+        assert!(partial_puzzle.apply_sat_model("s UNSATISFIABLE\n").is_err());
+    }
+}
@@ -1,37 +1,63 @@
 // Copyright 2022 by Daniel Winkelman. All rights reserved.
 
-use std::collections::BTreeSet;
+use crate::ks::{board::Shape, error::SolverError, util::popcnt64};
+use std::{cell::RefCell, collections::BTreeSet};
 
-pub fn cage_can_have_uniqueness(cells: &Vec<usize>) -> bool {
+/// Flags for the states of get_placeable_values
+const REACHABLE: u8 = 1;
+const COMPLETABLE: u8 = 2;
+
+thread_local! {
+    /* Kept between calls, since there is a state for every subset of the values */
+    static STATE_FLAGS: RefCell<Vec<u8>> = const { RefCell::new(vec![]) };
+}
+
+pub fn cage_can_have_uniqueness(shape: &Shape, cells: &[usize]) -> bool {
     let row_set = cells
         .iter()
-        .map(|cell_index| cell_index / 9)
+        .map(|cell_index| shape.get_row(*cell_index))
         .collect::<BTreeSet<usize>>();
     let col_set = cells
         .iter()
-        .map(|cell_index| cell_index % 9)
+        .map(|cell_index| shape.get_col(*cell_index))
+        .collect::<BTreeSet<usize>>();
+    let box_set = cells
+        .iter()
+        .map(|cell_index| shape.get_box(*cell_index))
         .collect::<BTreeSet<usize>>();
-    row_set.len() == 1 || col_set.len() == 1
+    row_set.len() == 1 || col_set.len() == 1 || box_set.len() == 1
+}
+
+pub fn cells_share_house(shape: &Shape, a: usize, b: usize) -> bool {
+    shape.get_row(a) == shape.get_row(b)
+        || shape.get_col(a) == shape.get_col(b)
+        || shape.get_box(a) == shape.get_box(b)
 }
 
-pub fn get_combinations(num_cells: usize, sum: usize) -> Result<Vec<u64>, ()> {
+/// Get the sets of distinct values from 1 to max_value with the number of cells and the sum
+pub fn get_combinations(
+    num_cells: usize,
+    sum: usize,
+    max_value: usize,
+) -> Result<Vec<u64>, SolverError> {
     fn recurse(
+        max_value: usize,
         num_cells: usize,
         sum: usize,
         current_value: usize,
         accum: u64,
         output: &mut Vec<u64>,
-    ) -> Result<(), ()> {
+    ) -> Option<()> {
         if num_cells == 1 {
             assert!(current_value <= sum);
             output.push(accum | (1 << sum));
-            Ok(())
+            Some(())
         } else {
             let lower_limit = {
-                let forced_max =
-                    (9 - (num_cells - 1)) * (num_cells - 1) + num_cells * (num_cells - 1) / 2;
+                let forced_max = max_value.checked_sub(num_cells - 1)? * (num_cells - 1)
+                    + num_cells * (num_cells - 1) / 2;
                 if forced_max < sum {
-                    (sum - forced_max).min(9)
+                    (sum - forced_max).min(max_value)
                 } else {
                     1
                 }
@@ -41,28 +67,90 @@ pub fn get_combinations(num_cells: usize, sum: usize) -> Result<Vec<u64>, ()> {
                     (current_value - 1) * (num_cells - 1) + num_cells * (num_cells - 1) / 2;
                 if forced_min < sum {
                     let ceiling = (sum - num_cells * (num_cells - 1) / 2) / num_cells;
-                    Ok((sum - forced_min).min(ceiling).min(9))
+                    Some((sum - forced_min).min(ceiling).min(max_value))
                 } else {
-                    Err(())
+                    None
                 }
             }?;
             for i in current_value.max(lower_limit)..=upper_limit {
-                recurse(num_cells - 1, sum - i, i + 1, accum | (1 << i), output)?;
+                recurse(
+                    max_value,
+                    num_cells - 1,
+                    sum - i,
+                    i + 1,
+                    accum | (1 << i),
+                    output,
+                )?;
             }
-            Ok(())
+            Some(())
         }
     }
+    /* The search assumes that the sum can be reached */
+    let min_sum = num_cells * (num_cells + 1) / 2;
+    let max_sum =
+        (num_cells * max_value).saturating_sub(num_cells * num_cells.saturating_sub(1) / 2);
+    if num_cells == 0 || num_cells > max_value || sum < min_sum || sum > max_sum {
+        return Err(SolverError::impossible_sum(num_cells, sum));
+    }
     let mut output = vec![];
-    recurse(num_cells, sum, 1, 0, &mut output)?;
+    recurse(max_value, num_cells, sum, 1, 0, &mut output)
+        .ok_or_else(|| SolverError::impossible_sum(num_cells, sum))?;
     Ok(output)
 }
 
-pub fn get_combinations_union(num_cells: usize, sum: usize) -> Result<u64, ()> {
-    Ok(get_combinations(num_cells, sum)?
+pub fn get_combinations_union(
+    num_cells: usize,
+    sum: usize,
+    max_value: usize,
+) -> Result<u64, SolverError> {
+    Ok(get_combinations(num_cells, sum, max_value)?
         .into_iter()
         .fold(0, |accum, x| accum | x))
 }
 
+/// For each cell (given by the bitmask of its possible values), find the values it can take in at
+/// least one assignment of distinct values to all cells that uses exactly one of the combinations
+pub fn get_placeable_values(cells: &[u64], combinations: &[u64]) -> Vec<u64> {
+    /* A state is the set of values assigned to the first k cells, where k is its population */
+    let all_values = cells.iter().fold(0, |accum, cell| accum | cell);
+    let num_states = 1 << (64 - all_values.leading_zeros());
+    STATE_FLAGS.with_borrow_mut(|flags| {
+        flags.clear();
+        flags.resize(num_states, 0);
+        flags[0] = REACHABLE;
+        for state in 0..num_states {
+            let num_assigned = popcnt64(state as u64);
+            if flags[state] & REACHABLE != 0 && num_assigned < cells.len() {
+                for value in PossibleValues::new(cells[num_assigned] & !(state as u64)) {
+                    flags[state | (1 << value)] |= REACHABLE;
+                }
+            }
+        }
+
+        /* Walk backwards from the combinations, keeping only transitions that can be completed */
+        for combination in combinations {
+            if let Some(flag) = flags.get_mut(*combination as usize) {
+                if *flag & REACHABLE != 0 {
+                    *flag |= COMPLETABLE;
+                }
+            }
+        }
+        let mut output = vec![0; cells.len()];
+        for state in (0..num_states).rev() {
+            let num_assigned = popcnt64(state as u64);
+            if flags[state] & REACHABLE != 0 && num_assigned < cells.len() {
+                for value in PossibleValues::new(cells[num_assigned] & !(state as u64)) {
+                    if flags[state | (1 << value)] & COMPLETABLE != 0 {
+                        flags[state] |= COMPLETABLE;
+                        output[num_assigned] |= 1 << value;
+                    }
+                }
+            }
+        }
+        output
+    })
+}
+
 pub struct PossibleValues {
     bitmask: u64,
     index: usize,
@@ -99,18 +187,18 @@ impl Iterator for PossibleValues {
 mod tests {
     use crate::ks::combinations::PossibleValues;
 
-    use super::{get_combinations, get_combinations_union};
+    use super::{get_combinations, get_combinations_union, get_placeable_values};
 
     #[test]
     fn test_single_cell() {
-        let c = get_combinations(1, 5).unwrap();
+        let c = get_combinations(1, 5, 9).unwrap();
         assert_eq!(c.len(), 1);
         assert_eq!(PossibleValues::new(c[0]).collect::<Vec<usize>>(), vec![5]);
     }
 
     #[test]
     fn test_double_cell_odd() {
-        let c = get_combinations(2, 13).unwrap();
+        let c = get_combinations(2, 13, 9).unwrap();
         assert_eq!(c.len(), 3);
         assert_eq!(
             PossibleValues::new(c[0]).collect::<Vec<usize>>(),
@@ -128,7 +216,7 @@ mod tests {
 
     #[test]
     fn test_double_cell_even() {
-        let c = get_combinations(2, 14).unwrap();
+        let c = get_combinations(2, 14, 9).unwrap();
         assert_eq!(c.len(), 2);
         assert_eq!(
             PossibleValues::new(c[0]).collect::<Vec<usize>>(),
@@ -143,21 +231,54 @@ mod tests {
     #[test]
     fn test_multiple_cells() {
         /* https://en.wikipedia.org/wiki/Killer_sudoku#Cage_total_tables */
-        assert_eq!(get_combinations(3, 15).unwrap().len(), 8);
-        assert_eq!(get_combinations(4, 15).unwrap().len(), 6);
-        assert_eq!(get_combinations(5, 15).unwrap().len(), 1);
-        assert_eq!(get_combinations(5, 25).unwrap().len(), 12);
-        assert_eq!(get_combinations(6, 25).unwrap().len(), 4);
-        assert_eq!(get_combinations(7, 33).unwrap().len(), 3);
-        assert_eq!(get_combinations(8, 40).unwrap().len(), 1);
-        assert_eq!(get_combinations(9, 45).unwrap().len(), 1);
+        assert_eq!(get_combinations(3, 15, 9).unwrap().len(), 8);
+        assert_eq!(get_combinations(4, 15, 9).unwrap().len(), 6);
+        assert_eq!(get_combinations(5, 15, 9).unwrap().len(), 1);
+        assert_eq!(get_combinations(5, 25, 9).unwrap().len(), 12);
+        assert_eq!(get_combinations(6, 25, 9).unwrap().len(), 4);
+        assert_eq!(get_combinations(7, 33, 9).unwrap().len(), 3);
+        assert_eq!(get_combinations(8, 40, 9).unwrap().len(), 1);
+        assert_eq!(get_combinations(9, 45, 9).unwrap().len(), 1);
+
+        /* Smaller and larger grids */
+        assert_eq!(get_combinations(2, 11, 6).unwrap().len(), 1);
+        assert_eq!(get_combinations(2, 11, 9).unwrap().len(), 4);
+        assert_eq!(get_combinations(2, 31, 16).unwrap().len(), 1);
+        assert_eq!(get_combinations(16, 136, 16).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_impossible_sums() {
+        assert!(get_combinations(1, 0, 9).is_err());
+        assert!(get_combinations(1, 10, 9).is_err());
+        assert!(get_combinations(3, 5, 9).is_err());
+        assert!(get_combinations(3, 25, 9).is_err());
+        assert!(get_combinations(10, 55, 9).is_err());
     }
 
     #[test]
     fn test_union() {
         assert_eq!(
-            PossibleValues::new(get_combinations_union(2, 13).unwrap()).collect::<Vec<usize>>(),
+            PossibleValues::new(get_combinations_union(2, 13, 9).unwrap()).collect::<Vec<usize>>(),
             vec![4, 5, 6, 7, 8, 9]
         );
     }
+
+    #[test]
+    fn test_placeable_values() {
+        /* 3 cells summing to 7 must be {1, 2, 4}; the 4 can only go in the last cell */
+        let cells = [0b0110, 0b0110, 0b11110];
+        let placeable = get_placeable_values(&cells, &get_combinations(3, 7, 9).unwrap());
+        assert_eq!(placeable, vec![0b0110, 0b0110, 0b10000]);
+
+        /* No combination fits */
+        let cells = [0b0010, 0b0010];
+        let placeable = get_placeable_values(&cells, &get_combinations(2, 3, 9).unwrap());
+        assert_eq!(placeable, vec![0, 0]);
+
+        /* Nothing is left over from the previous searches on this thread */
+        let cells = [0b0110, 0b0110, 0b11110];
+        let placeable = get_placeable_values(&cells, &get_combinations(3, 7, 9).unwrap());
+        assert_eq!(placeable, vec![0b0110, 0b0110, 0b10000]);
+    }
 }
@@ -0,0 +1,73 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::{collections::BTreeSet, sync::Arc};
+
+use crate::ks::{
+    cancel::CancellationToken, observer::Observer, pool::get_default_num_threads, step::Technique,
+};
+
+/// Which techniques the solver may use and how far it may go
+#[derive(Clone, Debug)]
+pub struct SolverConfig {
+    /// Techniques which may be applied; guessing is controlled separately
+    pub techniques: BTreeSet<Technique>,
+    /// Guess when the techniques get stuck, rather than failing
+    pub allow_guessing: bool,
+    pub max_guess_depth: usize,
+    /// Largest cage derived from the houses or from linear combinations of the cages
+    pub max_derived_cage_size: usize,
+    /// Largest cage of cells which stick out of a house or a union of houses
+    pub max_outie_size: usize,
+    /// Worker threads for searching guesses
+    pub num_threads: usize,
+    pub cancellation: CancellationToken,
+    pub observer: Option<Arc<dyn Observer>>,
+}
+
+impl Default for SolverConfig {
+    fn default() -> Self {
+        Self {
+            techniques: Technique::ALL
+                .into_iter()
+                .filter(|technique| ![Technique::Guess, Technique::Backtrack].contains(technique))
+                .collect(),
+            allow_guessing: true,
+            max_guess_depth: 10,
+            max_derived_cage_size: 8,
+            max_outie_size: 4,
+            num_threads: get_default_num_threads(),
+            cancellation: CancellationToken::default(),
+            observer: None,
+        }
+    }
+}
+
+impl SolverConfig {
+    /// Every technique, but no guessing
+    #[allow(unused)]
+    pub fn logic_only() -> Self {
+        Self {
+            allow_guessing: false,
+            ..Self::default()
+        }
+    }
+
+    /// Only the techniques of the easiest difficulty, and no guessing
+    #[allow(unused)]
+    pub fn basic() -> Self {
+        Self {
+            techniques: Technique::ALL
+                .into_iter()
+                .filter(|technique| technique.get_difficulty() == 1)
+                .collect(),
+            ..Self::logic_only()
+        }
+    }
+
+    pub fn allows(&self, technique: Technique) -> bool {
+        match technique {
+            Technique::Guess | Technique::Backtrack => self.allow_guessing,
+            _ => self.techniques.contains(&technique),
+        }
+    }
+}
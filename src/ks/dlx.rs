@@ -0,0 +1,295 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::collections::BTreeSet;
+
+use crate::ks::{
+    board::Board,
+    cage::Cage,
+    cancel::CancellationToken,
+    cell::Cell,
+    combinations::{cage_can_have_uniqueness, get_combinations},
+    error::SolverError,
+};
+
+/// Sparse 0/1 matrix of doubly-linked nodes, as in Knuth's Dancing Links; node 0 is the root and
+/// nodes 1 to the number of columns are the column headers
+struct Matrix {
+    left: Vec<usize>,
+    right: Vec<usize>,
+    up: Vec<usize>,
+    down: Vec<usize>,
+    column: Vec<usize>,
+    row: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl Matrix {
+    fn new(num_columns: usize) -> Self {
+        let num_nodes = num_columns + 1;
+        Self {
+            left: (0..num_nodes)
+                .map(|node| (node + num_nodes - 1) % num_nodes)
+                .collect(),
+            right: (0..num_nodes).map(|node| (node + 1) % num_nodes).collect(),
+            up: (0..num_nodes).collect(),
+            down: (0..num_nodes).collect(),
+            column: (0..num_nodes).collect(),
+            row: vec![usize::MAX; num_nodes],
+            size: vec![0; num_nodes],
+        }
+    }
+
+    fn add_row(&mut self, row_index: usize, columns: &[usize]) {
+        let first = self.column.len();
+        let num_nodes = columns.len();
+        for (i, column) in columns.iter().enumerate() {
+            let header = column + 1;
+            let node = first + i;
+            self.left.push(first + (i + num_nodes - 1) % num_nodes);
+            self.right.push(first + (i + 1) % num_nodes);
+            self.up.push(self.up[header]);
+            self.down.push(header);
+            self.down[self.up[header]] = node;
+            self.up[header] = node;
+            self.column.push(header);
+            self.row.push(row_index);
+            self.size[header] += 1;
+        }
+    }
+
+    fn cover(&mut self, header: usize) {
+        self.right[self.left[header]] = self.right[header];
+        self.left[self.right[header]] = self.left[header];
+        let mut i = self.down[header];
+        while i != header {
+            let mut j = self.right[i];
+            while j != i {
+                self.down[self.up[j]] = self.down[j];
+                self.up[self.down[j]] = self.up[j];
+                self.size[self.column[j]] -= 1;
+                j = self.right[j];
+            }
+            i = self.down[i];
+        }
+    }
+
+    fn uncover(&mut self, header: usize) {
+        let mut i = self.up[header];
+        while i != header {
+            let mut j = self.left[i];
+            while j != i {
+                self.size[self.column[j]] += 1;
+                self.down[self.up[j]] = j;
+                self.up[self.down[j]] = j;
+                j = self.left[j];
+            }
+            i = self.up[i];
+        }
+        self.right[self.left[header]] = header;
+        self.left[self.right[header]] = header;
+    }
+
+    /// Algorithm X, choosing the column with the fewest rows at each level
+    fn search(
+        &mut self,
+        partial: &mut Vec<usize>,
+        limit: usize,
+        cancellation: &CancellationToken,
+        output: &mut Vec<Vec<usize>>,
+    ) -> Result<(), SolverError> {
+        cancellation.check()?;
+        if self.right[0] == 0 {
+            output.push(partial.clone());
+            return Ok(());
+        }
+        let mut header = self.right[0];
+        let mut j = self.right[header];
+        while j != 0 {
+            if self.size[j] < self.size[header] {
+                header = j;
+            }
+            j = self.right[j];
+        }
+        self.cover(header);
+        let mut i = self.down[header];
+        while i != header && output.len() < limit {
+            partial.push(self.row[i]);
+            let mut j = self.right[i];
+            while j != i {
+                self.cover(self.column[j]);
+                j = self.right[j];
+            }
+            let result = self.search(partial, limit, cancellation, output);
+            let mut j = self.left[i];
+            while j != i {
+                self.uncover(self.column[j]);
+                j = self.left[j];
+            }
+            partial.pop();
+            result?;
+            i = self.down[i];
+        }
+        self.uncover(header);
+        Ok(())
+    }
+}
+
+/// Find up to limit sets of rows which together contain each column exactly once
+pub fn solve_exact_cover(
+    num_columns: usize,
+    rows: &[Vec<usize>],
+    limit: usize,
+    cancellation: &CancellationToken,
+) -> Result<Vec<Vec<usize>>, SolverError> {
+    let mut matrix = Matrix::new(num_columns);
+    for (row_index, columns) in rows.iter().enumerate() {
+        matrix.add_row(row_index, columns);
+    }
+    let mut output = vec![];
+    matrix.search(&mut vec![], limit, cancellation, &mut output)?;
+    Ok(output)
+}
+
+/// Find up to limit solutions of the board by brute force. Each row of the matrix either places a
+/// value in a cell or chooses a combination for a cage with uniqueness; the columns require each
+/// cell to be filled, each value to appear once in each house, and each value of each cage to
+/// appear once if it is in the chosen combination and not at all otherwise.
+pub fn solve_board_by_exact_cover(
+    board: &Board,
+    cages: &BTreeSet<Cage>,
+    limit: usize,
+    cancellation: &CancellationToken,
+) -> Result<Vec<Board>, SolverError> {
+    let shape = board.shape();
+    let (size, num_cells) = (shape.size(), shape.num_cells());
+    let num_cell_columns = num_cells * 4;
+    /* The houses have their own columns */
+    let cages = cages
+        .iter()
+        .filter(|cage| {
+            cage.uniqueness
+                && !(cage.cells.len() == size && cage_can_have_uniqueness(&shape, &cage.cells))
+        })
+        .collect::<Vec<&Cage>>();
+    let cage_column =
+        |cage_index: usize, value: usize| num_cell_columns + cage_index * (size + 1) + value;
+    let mut cell_cages = vec![vec![]; num_cells];
+    for (cage_index, cage) in cages.iter().enumerate() {
+        for cell_index in cage.cells.iter() {
+            cell_cages[*cell_index].push(cage_index);
+        }
+    }
+
+    let mut rows = vec![];
+    let mut placements = vec![];
+    for (cell_index, cell) in board.iter().enumerate() {
+        let (row, col) = (shape.get_row(cell_index), shape.get_col(cell_index));
+        let box_index = shape.get_box(cell_index);
+        for value in cell.possible_values() {
+            let mut columns = vec![
+                cell_index,
+                num_cells + row * size + value - 1,
+                num_cells * 2 + col * size + value - 1,
+                num_cells * 3 + box_index * size + value - 1,
+            ];
+            columns.extend(
+                cell_cages[cell_index]
+                    .iter()
+                    .map(|cage_index| cage_column(*cage_index, value)),
+            );
+            rows.push(columns);
+            placements.push(Some((cell_index, value)));
+        }
+    }
+    for (cage_index, cage) in cages.iter().enumerate() {
+        for combination in get_combinations(cage.cells.len(), cage.sum, size)? {
+            let mut columns = vec![cage_column(cage_index, 0)];
+            columns.extend(
+                (1..=size)
+                    .filter(|value| (combination >> value) & 1 == 0)
+                    .map(|value| cage_column(cage_index, value)),
+            );
+            rows.push(columns);
+            placements.push(None);
+        }
+    }
+
+    Ok(solve_exact_cover(
+        num_cell_columns + cages.len() * (size + 1),
+        &rows,
+        limit,
+        cancellation,
+    )?
+    .into_iter()
+    .map(|solution| {
+        let mut board = board.clone();
+        for (cell_index, value) in solution
+            .into_iter()
+            .filter_map(|row_index| placements[row_index])
+        {
+            board[cell_index] = Cell::new(size);
+            board[cell_index].restrict_to(1 << value).unwrap();
+        }
+        board
+    })
+    .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ks::{io::SerializablePuzzle, puzzle::Puzzle};
+
+    #[test]
+    fn knuth_example() {
+        let rows = vec![
+            vec![2, 4, 5],
+            vec![0, 3, 6],
+            vec![1, 2, 5],
+            vec![0, 3],
+            vec![1, 6],
+            vec![3, 4, 6],
+        ];
+        let cancellation = CancellationToken::default();
+        assert_eq!(
+            solve_exact_cover(7, &rows, 10, &cancellation).unwrap(),
+            vec![vec![3, 0, 4]]
+        );
+        assert_eq!(
+            solve_exact_cover(8, &rows, 10, &cancellation).unwrap(),
+            Vec::<Vec<usize>>::new()
+        );
+    }
+
+    #[test]
+    fn board_by_exact_cover() {
+        let serialized_puzzle =
+            SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap();
+        let cell_values = serialized_puzzle.cell_values.clone();
+        let puzzle = Puzzle::from_serializable(serialized_puzzle).unwrap();
+        let solutions = solve_board_by_exact_cover(
+            &puzzle.board,
+            &Puzzle::get_houses(&puzzle.board.shape()),
+            2,
+            &CancellationToken::default(),
+        )
+        .unwrap();
+        assert_eq!(solutions.len(), 2);
+
+        let mut cages = Puzzle::get_houses(&puzzle.board.shape());
+        cages.extend(
+            SerializablePuzzle::from_str(include_str!("test/puzzle_0.json"))
+                .unwrap()
+                .cages
+                .into_iter()
+                .map(|cage| Cage::from_parts(cage.cell_indices, cage.sum, true)),
+        );
+        let solutions =
+            solve_board_by_exact_cover(&puzzle.board, &cages, 2, &CancellationToken::default())
+                .unwrap();
+        assert_eq!(solutions.len(), 1);
+        for (cell, value) in solutions[0].iter().zip(cell_values.iter()) {
+            assert_eq!(cell.get_solution(), Some(*value));
+        }
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::{error::Error, fmt::Display};
+
+use crate::ks::{board::Board, cage::Cage};
+
+/// Where an error was found, as far as is known
+#[derive(Clone, Debug, Default)]
+pub struct Location {
+    pub cell_index: Option<usize>,
+    pub cage: Option<Cage>,
+    pub board: Option<Board>,
+}
+
+#[derive(Clone, Debug)]
+pub enum SolverError {
+    /// A cell has no possible values left, or the remaining values cannot satisfy a cage
+    Contradiction(Location),
+    /// No combination of distinct values has the number of cells and the sum
+    ImpossibleSum {
+        num_cells: usize,
+        sum: usize,
+        location: Location,
+    },
+    /// Guesses were nested deeper than the limit without reaching a solution or a contradiction
+    RecursionLimit { depth: usize, location: Location },
+    /// The techniques allowed made no more progress and guessing was not allowed
+    Stuck(Location),
+    /// The search was cancelled or ran past its deadline; the board is the furthest the techniques
+    /// got before guessing
+    Cancelled(Location),
+    /// The puzzle has no solution or more than one
+    NotUnique { num_solutions: usize },
+    /// The output of an external solver could not be read as a solution
+    InvalidModel,
+    /// A puzzle or snapshot being loaded does not fit its grid, such as a given which is out of
+    /// range
+    InvalidInput(Location),
+}
+
+impl SolverError {
+    pub fn contradiction() -> Self {
+        SolverError::Contradiction(Location::default())
+    }
+
+    pub fn invalid_input() -> Self {
+        SolverError::InvalidInput(Location::default())
+    }
+
+    pub fn impossible_sum(num_cells: usize, sum: usize) -> Self {
+        SolverError::ImpossibleSum {
+            num_cells,
+            sum,
+            location: Location::default(),
+        }
+    }
+
+    pub fn get_location(&self) -> Option<&Location> {
+        match self {
+            SolverError::Contradiction(location)
+            | SolverError::Stuck(location)
+            | SolverError::Cancelled(location)
+            | SolverError::InvalidInput(location)
+            | SolverError::ImpossibleSum { location, .. }
+            | SolverError::RecursionLimit { location, .. } => Some(location),
+            SolverError::NotUnique { .. } | SolverError::InvalidModel => None,
+        }
+    }
+
+    fn get_location_mut(&mut self) -> Option<&mut Location> {
+        match self {
+            SolverError::Contradiction(location)
+            | SolverError::Stuck(location)
+            | SolverError::Cancelled(location)
+            | SolverError::InvalidInput(location)
+            | SolverError::ImpossibleSum { location, .. }
+            | SolverError::RecursionLimit { location, .. } => Some(location),
+            SolverError::NotUnique { .. } | SolverError::InvalidModel => None,
+        }
+    }
+
+    /// Record the cell where the error was found, unless one is already known
+    pub fn at_cell(mut self, cell_index: usize) -> Self {
+        if let Some(location) = self.get_location_mut() {
+            location.cell_index.get_or_insert(cell_index);
+        }
+        self
+    }
+
+    /// Record the cage being processed when the error was found, unless one is already known
+    pub fn in_cage(mut self, cage: &Cage) -> Self {
+        if let Some(location) = self.get_location_mut() {
+            location.cage.get_or_insert_with(|| cage.clone());
+        }
+        self
+    }
+
+    /// Record a snapshot of the board, and the first cell with no possible values if no cell is
+    /// already known
+    pub fn on_board(mut self, board: &Board) -> Self {
+        if let Some(location) = self.get_location_mut() {
+            if location.cell_index.is_none() {
+                location.cell_index = board.iter().position(|cell| cell.get_bits() == 0);
+            }
+            location.board.get_or_insert_with(|| board.clone());
+        }
+        self
+    }
+}
+
+impl Display for SolverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SolverError::Contradiction(_) => write!(f, "Contradiction")?,
+            SolverError::ImpossibleSum { num_cells, sum, .. } => {
+                write!(f, "No combination of {} values sums to {}", num_cells, sum)?
+            }
+            SolverError::RecursionLimit { depth, .. } => {
+                write!(f, "Guesses nested deeper than {}", depth)?
+            }
+            SolverError::Stuck(_) => write!(f, "Stuck without guessing")?,
+            SolverError::Cancelled(_) => write!(f, "Cancelled")?,
+            SolverError::NotUnique { num_solutions } => {
+                write!(f, "Expected 1 solution but found {}", num_solutions)?
+            }
+            SolverError::InvalidModel => write!(f, "Invalid model")?,
+            SolverError::InvalidInput(_) => write!(f, "Invalid input")?,
+        }
+        if let Some(location) = self.get_location() {
+            if let Some(cell_index) = location.cell_index {
+                write!(f, " at cell {}", cell_index)?;
+            }
+            if let Some(cage) = &location.cage {
+                write!(f, " in cage {}", cage)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for SolverError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location() {
+        let mut board = Board::default();
+        let error = board[12].restrict_to(0).unwrap_err();
+        let error = error
+            .in_cage(&Cage::from_parts(vec![12, 13], 3, true))
+            .on_board(&board)
+            .in_cage(&Cage::from_parts(vec![11, 12], 4, true));
+        let location = error.get_location().unwrap();
+        assert_eq!(location.cell_index, Some(12));
+        assert_eq!(location.cage.as_ref().unwrap().cells, vec![12, 13]);
+        assert_eq!(
+            format!("{}", error),
+            "Contradiction at cell 12 in cage [12, 13] = 3"
+        );
+    }
+}
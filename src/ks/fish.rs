@@ -0,0 +1,165 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::collections::BTreeSet;
+
+use crate::ks::{board::Board, cage::Cage, error::SolverError, util::popcnt64};
+
+/// If a value is confined to the same `size` columns within `size` rows (or vice versa), it
+/// cannot appear anywhere else in those columns; size 2 is an X-Wing, 3 a Swordfish, and 4 a
+/// Jellyfish. Returns true if progress was made, adding the base and cover lines to cages.
+pub fn restrict_by_fish(
+    board: &mut Board,
+    size: usize,
+    cages: &mut BTreeSet<Cage>,
+) -> Result<bool, SolverError> {
+    /* Find the sets of base lines whose positions are covered by exactly `size` cover lines */
+    fn find_fish(
+        index: usize,
+        remaining: usize,
+        size: usize,
+        accum: (u64, u64),
+        positions_by_line: &[u64],
+        output: &mut Vec<(u64, u64)>,
+    ) {
+        let (base_lines, cover_lines) = accum;
+        if popcnt64(cover_lines) > size {
+            return;
+        }
+        if remaining == 0 {
+            output.push(accum);
+            return;
+        }
+        for line in index..=(positions_by_line.len() - remaining) {
+            let positions = positions_by_line[line];
+            if positions != 0 && popcnt64(positions) <= size {
+                find_fish(
+                    line + 1,
+                    remaining - 1,
+                    size,
+                    (base_lines | (1 << line), cover_lines | positions),
+                    positions_by_line,
+                    output,
+                );
+            }
+        }
+    }
+
+    let shape = board.shape();
+    let num_lines = shape.size();
+    let mut progress = false;
+    for value in 1..=num_lines {
+        for transpose in [false, true] {
+            let get_cell_index = |line: usize, position: usize| {
+                if transpose {
+                    position * num_lines + line
+                } else {
+                    line * num_lines + position
+                }
+            };
+            /* Base lines run along the line index and cover lines across it */
+            let get_house = |line: usize, across: bool| {
+                let cells = (0..num_lines)
+                    .map(|position| match across {
+                        false => get_cell_index(line, position),
+                        true => get_cell_index(position, line),
+                    })
+                    .collect();
+                Cage::from_parts(cells, shape.house_sum(), true)
+            };
+            let mut positions_by_line = vec![0; num_lines];
+            for (line, positions) in positions_by_line.iter_mut().enumerate() {
+                *positions = (0..num_lines)
+                    .filter(|position| board[get_cell_index(line, *position)].allows(value))
+                    .fold(0, |accum, position| accum | (1 << position));
+                if *positions == 0 {
+                    return Err(SolverError::contradiction().in_cage(&get_house(line, false)));
+                }
+            }
+            let mut fish = vec![];
+            find_fish(0, size, size, (0, 0), &positions_by_line, &mut fish);
+            for (base_lines, cover_lines) in fish {
+                if popcnt64(cover_lines) < size {
+                    /* The base lines cannot each have the value */
+                    return Err(SolverError::contradiction());
+                }
+                let mut fish_progress = false;
+                for line in (0..num_lines).filter(|line| (base_lines >> line) & 1 == 0) {
+                    for position in
+                        (0..num_lines).filter(|position| (cover_lines >> position) & 1 == 1)
+                    {
+                        let cell_index = get_cell_index(line, position);
+                        if board[cell_index].allows(value) {
+                            board[cell_index]
+                                .restrict_to(!(1 << value))
+                                .map_err(|error| error.at_cell(cell_index))?;
+                            fish_progress = true;
+                        }
+                    }
+                }
+                if fish_progress {
+                    for line in (0..num_lines).filter(|line| (base_lines >> line) & 1 == 1) {
+                        cages.insert(get_house(line, false));
+                    }
+                    for line in (0..num_lines).filter(|line| (cover_lines >> line) & 1 == 1) {
+                        cages.insert(get_house(line, true));
+                    }
+                    progress = true;
+                }
+            }
+        }
+    }
+    Ok(progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn x_wing() {
+        /* Confine 1 to columns 2 and 6 in rows 0 and 4 */
+        let mut board = Board::default();
+        for row in [0, 4] {
+            for col in (0..9).filter(|col| *col != 2 && *col != 6) {
+                board[row * 9 + col].restrict_to(!(1 << 1)).unwrap();
+            }
+        }
+        let mut cages = BTreeSet::new();
+        assert!(restrict_by_fish(&mut board, 2, &mut cages).unwrap());
+        let cage_cells = cages
+            .iter()
+            .map(|cage| cage.cells.clone())
+            .collect::<BTreeSet<Vec<usize>>>();
+        let houses = [(0..9).collect::<Vec<usize>>(), (36..45).collect()]
+            .into_iter()
+            .chain([2, 6].map(|col| (0..9).map(|row| row * 9 + col).collect()))
+            .collect::<BTreeSet<Vec<usize>>>();
+        assert_eq!(cage_cells, houses);
+        for row in 0..9 {
+            let expected = row == 0 || row == 4;
+            assert_eq!(board[row * 9 + 2].allows(1), expected);
+            assert_eq!(board[row * 9 + 6].allows(1), expected);
+            assert!(board[row * 9 + 3].allows(1) != expected);
+        }
+        assert!(!restrict_by_fish(&mut board, 2, &mut BTreeSet::new()).unwrap());
+    }
+
+    #[test]
+    fn swordfish() {
+        /* Confine 5 to rows 1, 3, and 8 in columns 0, 4, and 7, without forming an X-Wing */
+        let mut board = Board::default();
+        for (col, rows) in [(0, [1, 3]), (4, [3, 8]), (7, [1, 8])] {
+            for row in (0..9).filter(|row| !rows.contains(row)) {
+                board[row * 9 + col].restrict_to(!(1 << 5)).unwrap();
+            }
+        }
+        assert!(!restrict_by_fish(&mut board, 2, &mut BTreeSet::new()).unwrap());
+        assert!(restrict_by_fish(&mut board, 3, &mut BTreeSet::new()).unwrap());
+        for col in (0..9).filter(|col| ![0, 4, 7].contains(col)) {
+            for row in [1, 3, 8] {
+                assert!(!board[row * 9 + col].allows(5));
+            }
+            assert!(board[col].allows(5));
+        }
+    }
+}
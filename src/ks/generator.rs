@@ -1,6 +1,9 @@
 // Copyright 2022 by Daniel Winkelman. All rights reserved.
 
 use crate::ks::{
+    board::Shape,
+    config::SolverConfig,
+    error::SolverError,
     io::{SerializableCage, SerializablePuzzle},
     puzzle::Puzzle,
 };
@@ -9,6 +12,12 @@ use std::collections::{BTreeMap, BTreeSet};
 
 use rand::{seq::SliceRandom, thread_rng, Rng};
 
+/// The largest cage the generator makes, unless the grid is too small for it
+const MAX_CAGE_SIZE: usize = 7;
+
+/// Most rows whose values are shuffled when generating a random grid
+const MAX_RESOLVED_ROWS: usize = 6;
+
 #[derive(Debug, Clone)]
 struct Cage {
     cells: BTreeSet<usize>,
@@ -17,22 +26,26 @@ struct Cage {
 
 #[derive(Debug, Clone)]
 pub struct Generator {
-    numbers: [usize; 81],
+    shape: Shape,
+    numbers: Vec<usize>,
     cages: BTreeMap<usize, Cage>,
+    givens: BTreeSet<usize>,
+    config: SolverConfig,
 }
 
 impl Generator {
     /// Initialize the generator with the canonical solution
-    pub fn new_canonical() -> Self {
-        let mut canonical_solution = [0; 81];
-        for row in 0..9 {
-            let offset = row * 3 + row / 3;
-            for col in 0..9 {
-                canonical_solution[row * 9 + col] = (col + offset) % 9 + 1;
+    pub fn new_canonical(shape: Shape) -> Self {
+        let size = shape.size();
+        let mut canonical_solution = vec![0; shape.num_cells()];
+        for row in 0..size {
+            let offset = (row % shape.box_rows) * shape.box_cols + row / shape.box_rows;
+            for col in 0..size {
+                canonical_solution[row * size + col] = (col + offset) % size + 1;
             }
         }
         /* All cells initially belong to a 1-cell cage whose index matches the cell index */
-        let mut cages = (0..81)
+        let mut cages = (0..shape.num_cells())
             .map(|i| {
                 (
                     i,
@@ -47,31 +60,41 @@ impl Generator {
             cages.get_mut(&a).unwrap().adjacent_cages.insert(b);
             cages.get_mut(&b).unwrap().adjacent_cages.insert(a);
         };
-        for row in 0..9 {
-            for col in 0..8 {
-                insert_merge(row * 9 + col, row * 9 + col + 1);
-                insert_merge(col * 9 + row, (col + 1) * 9 + row);
+        for row in 0..size {
+            for col in 0..(size - 1) {
+                insert_merge(row * size + col, row * size + col + 1);
+                insert_merge(col * size + row, (col + 1) * size + row);
             }
         }
         Self {
+            shape,
             numbers: canonical_solution,
             cages,
+            givens: BTreeSet::new(),
+            config: SolverConfig::default(),
         }
     }
 
-    pub fn new_random() -> Self {
-        let mut output = Self::new_canonical();
+    /// Only accept merges which leave the puzzle solvable with the config; unless guessing is
+    /// forbidden, any puzzle with a unique solution is accepted
+    #[allow(unused)]
+    pub fn set_config(&mut self, config: SolverConfig) {
+        self.config = config;
+    }
+
+    pub fn new_random(shape: Shape) -> Result<Self, SolverError> {
+        let mut output = Self::new_canonical(shape);
         for _ in 0..10 {
             output.renumber();
             output.shuffle_rows();
-            output.partial_resolution();
+            output.partial_resolution()?;
         }
-        output
+        Ok(output)
     }
 
     fn renumber(&mut self) {
         let sequence = {
-            let mut v = vec![1, 2, 3, 4, 5, 6, 7, 8, 9];
+            let mut v = (1..=self.shape.size()).collect::<Vec<usize>>();
             v.shuffle(&mut thread_rng());
             v
         };
@@ -80,22 +103,36 @@ impl Generator {
             .for_each(|value| *value = sequence[*value - 1]);
     }
 
+    /// Shuffle the bands of boxes, and the rows within each band
     fn shuffle_rows(&mut self) {
-        let sequence = vec![3, 5, 4, 6, 7, 8, 0, 2, 1];
-        let mut new_numbers = [0; 81];
+        let Shape { box_rows, .. } = self.shape;
+        let size = self.shape.size();
+        let mut rng = thread_rng();
+        let mut bands = (0..(size / box_rows)).collect::<Vec<usize>>();
+        bands.shuffle(&mut rng);
+        let sequence = bands
+            .into_iter()
+            .flat_map(|band| {
+                let mut rows = (band * box_rows..(band + 1) * box_rows).collect::<Vec<usize>>();
+                rows.shuffle(&mut rng);
+                rows
+            })
+            .collect::<Vec<usize>>();
+        let mut new_numbers = vec![0; self.numbers.len()];
         sequence.into_iter().enumerate().for_each(|(row, seq)| {
-            for i in 0..9 {
-                new_numbers[row * 9 + i] = self.numbers[seq * 9 + i];
+            for i in 0..size {
+                new_numbers[row * size + i] = self.numbers[seq * size + i];
             }
         });
         self.numbers = new_numbers;
     }
 
-    fn partial_resolution(&mut self) {
-        /* Choose three random numbers */
+    fn partial_resolution(&mut self) -> Result<(), SolverError> {
+        /* Choose four random numbers */
         let mut rng = thread_rng();
         let numbers = {
-            let mut first = vec![1, 2, 3, 4, 5, 6, 7, 8, 9]
+            let mut first = (1..=self.shape.size())
+                .collect::<Vec<usize>>()
                 .choose_multiple(&mut rng, 4)
                 .cloned()
                 .collect::<Vec<usize>>();
@@ -103,13 +140,16 @@ impl Generator {
             first
         };
 
-        /* Eliminate chosen numbers from part of the puzzle */
+        /* Eliminate chosen numbers from the bands after the first, but from no more than six rows
+        since larger grids have too many solutions to enumerate */
+        let band_len = self.shape.box_rows * self.shape.size();
+        let num_bands = (MAX_RESOLVED_ROWS / self.shape.box_rows).max(1);
         let cages = self
             .numbers
             .iter()
             .enumerate()
             .filter_map(|(i, value)| {
-                if i < 27 {
+                if !(band_len..band_len * (num_bands + 1)).contains(&i) {
                     Some((*value, vec![i]))
                 } else {
                     match numbers.iter().find(|n| *n == value) {
@@ -121,17 +161,35 @@ impl Generator {
             .collect();
 
         /* Find all solutions (there should be several) and chose a random one */
-        let mut puzzle = Puzzle::new();
+        let mut puzzle = Puzzle::with_shape(self.shape);
         puzzle.init_cages(cages, false);
-        let p = puzzle.solve().unwrap().choose(&mut rng).unwrap().clone();
+        let p = puzzle
+            .solve(&SolverConfig::default())?
+            .choose(&mut rng)
+            .unwrap()
+            .clone();
         self.numbers.iter_mut().enumerate().for_each(|(i, value)| {
             *value = p.board[i].get_solution().unwrap();
         });
+        Ok(())
+    }
+
+    /// Give away the values of some random cells, which allows more cages to be merged
+    #[allow(unused)]
+    pub fn add_givens(&mut self, num_givens: usize) {
+        let cell_indices = (0..self.shape.num_cells())
+            .filter(|cell_index| !self.givens.contains(cell_index))
+            .collect::<Vec<usize>>();
+        self.givens.extend(
+            cell_indices
+                .choose_multiple(&mut thread_rng(), num_givens)
+                .cloned(),
+        );
     }
 
     pub fn serialize(&self) -> SerializablePuzzle {
         SerializablePuzzle {
-            cell_values: self.numbers.iter().cloned().collect(),
+            cell_values: self.numbers.clone(),
             cages: self
                 .cages
                 .values()
@@ -144,6 +202,15 @@ impl Generator {
                     cell_indices: cage.cells.iter().cloned().collect(),
                 })
                 .collect(),
+            givens: (!self.givens.is_empty()).then(|| {
+                (0..self.shape.num_cells())
+                    .map(|cell_index| match self.givens.contains(&cell_index) {
+                        true => self.numbers[cell_index],
+                        false => 0,
+                    })
+                    .collect()
+            }),
+            shape: self.shape,
         }
     }
 
@@ -163,7 +230,12 @@ impl Generator {
         let a_values = get_cage_values(a);
         let b_values = get_cage_values(b);
         let ab_union_len = a_values.union(&b_values).cloned().count();
-        if a_values.len() + b_values.len() == ab_union_len && ab_union_len < 8 {
+        let accepted = a_values.len() + b_values.len() == ab_union_len
+            && ab_union_len <= MAX_CAGE_SIZE.min(self.shape.size() - 1);
+        if let Some(observer) = self.config.observer.as_deref() {
+            observer.on_merge(&self.cages[&a].cells, &self.cages[&b].cells, accepted);
+        }
+        if accepted {
             /* Insert contents of b into a */
             for adjacent_cage in self.cages[&b].adjacent_cages.clone() {
                 if adjacent_cage != a {
@@ -183,10 +255,8 @@ impl Generator {
 
             /* Rename all references to b so that they refer to a */
             self.cages.iter_mut().for_each(|(cage_index, cage)| {
-                if cage.adjacent_cages.remove(&b) {
-                    if *cage_index != a {
-                        cage.adjacent_cages.insert(a);
-                    }
+                if cage.adjacent_cages.remove(&b) && *cage_index != a {
+                    cage.adjacent_cages.insert(a);
                 }
             });
             true
@@ -241,34 +311,48 @@ impl Generator {
 
     fn merge_random_cages(&mut self) -> bool {
         match self.try_merge_random_cages() {
-            Ok(true) => return true,
-            Err(()) => return false,
+            Ok(true) => true,
+            Err(()) => false,
             _ => self.merge_random_cages(),
         }
     }
 
-    fn try_eliminate_cage(&mut self) -> Result<bool, ()> {
+    /// Returns None if there are no cages left to merge
+    fn try_eliminate_cage(&mut self) -> Result<Option<bool>, SolverError> {
         if self.merge_random_cages() {
             /* After a cage has been removed, try to solve */
-            let puzzle = Puzzle::from_serializable(self.serialize());
-            match puzzle.solve() {
-                Ok(solutions) => Ok(solutions.len() == 1),
-                Err(()) => panic!("The puzzle should not have contradictions"),
+            let puzzle = Puzzle::from_serializable(self.serialize())?;
+            let unique = if self.config.allow_guessing {
+                puzzle.has_unique_solution(&self.config)?
+            } else {
+                /* Logic alone only solves puzzles with a unique solution */
+                match puzzle.solve(&self.config) {
+                    Ok(_) => true,
+                    Err(SolverError::Stuck(_)) => false,
+                    Err(error) => return Err(error),
+                }
+            };
+            if let Some(observer) = self.config.observer.as_deref() {
+                observer.on_uniqueness_check(self.cages.len(), unique);
             }
+            Ok(Some(unique))
         } else {
-            Err(())
+            Ok(None)
         }
     }
 
-    pub fn eliminate_cage(&mut self) -> bool {
+    /// Merge two cages if the puzzle stays solvable; if cancelled, the generator is left with the
+    /// fewest cages found so far
+    pub fn eliminate_cage(&mut self) -> Result<bool, SolverError> {
+        self.config.cancellation.check()?;
         let mut copy = self.clone();
-        match copy.try_eliminate_cage() {
-            Ok(true) => {
+        match copy.try_eliminate_cage()? {
+            Some(true) => {
                 /* Success */
                 *self = copy;
-                true
+                Ok(true)
             }
-            Ok(false) => {
+            Some(false) => {
                 /* Copy updated adjacent cage state */
                 self.cages.iter_mut().for_each(|(cage_index, cage)| {
                     if let Some(other_cage) = copy.cages.get(cage_index) {
@@ -283,7 +367,7 @@ impl Generator {
                 });
                 self.eliminate_cage()
             }
-            Err(()) => false,
+            None => Ok(false),
         }
     }
 }
@@ -293,10 +377,11 @@ mod tests {
     use std::collections::BTreeSet;
 
     use super::Generator;
+    use crate::ks::{board::Shape, config::SolverConfig, error::SolverError, puzzle::Puzzle};
 
     #[test]
     fn merge_cages() {
-        let mut gen = Generator::new_canonical();
+        let mut gen = Generator::new_canonical(Shape::default());
         assert!(gen.try_merge_cages(0, 1));
         assert!(!gen.cages.contains_key(&1));
         assert_eq!(gen.cages[&0].cells, BTreeSet::from([0, 1]));
@@ -311,7 +396,7 @@ mod tests {
 
     #[test]
     fn merge_cages_unsuccessful() {
-        let mut gen = Generator::new_canonical();
+        let mut gen = Generator::new_canonical(Shape::default());
         assert!(gen.try_merge_cages(0, 1));
         assert!(gen.try_merge_cages(0, 2));
         assert!(gen.try_merge_cages(0, 3));
@@ -320,7 +405,7 @@ mod tests {
 
     #[test]
     fn merge_random_cages() {
-        let mut gen = Generator::new_canonical();
+        let mut gen = Generator::new_canonical(Shape::default());
         assert_eq!(gen.try_merge_random_cages(), Ok(true));
         assert_eq!(gen.cages.len(), 80);
         assert_eq!(
@@ -334,8 +419,8 @@ mod tests {
 
     #[test]
     fn merge_random_cages_loop() {
-        let mut gen = Generator::new_canonical();
-        assert_eq!(gen.merge_random_cages(), true);
+        let mut gen = Generator::new_canonical(Shape::default());
+        assert!(gen.merge_random_cages());
         assert_eq!(gen.cages.len(), 80);
         assert_eq!(
             gen.cages
@@ -345,4 +430,43 @@ mod tests {
             1
         );
     }
+
+    #[test]
+    fn eliminate_cage_cancelled() {
+        let mut gen = Generator::new_canonical(Shape::default());
+        let config = SolverConfig::default();
+        gen.set_config(config.clone());
+        assert!(gen.eliminate_cage().unwrap());
+        config.cancellation.cancel();
+        assert!(matches!(
+            gen.eliminate_cage(),
+            Err(SolverError::Cancelled(_))
+        ));
+        assert_eq!(gen.get_num_cages(), 80);
+    }
+
+    #[test]
+    fn add_givens() {
+        let mut gen = Generator::new_canonical(Shape::default());
+        assert!(gen.serialize().givens.is_none());
+        gen.add_givens(5);
+        let serialized_puzzle = gen.serialize();
+        let givens = serialized_puzzle.givens.unwrap();
+        assert_eq!(givens.iter().filter(|value| **value != 0).count(), 5);
+        for (given, value) in givens.iter().zip(serialized_puzzle.cell_values.iter()) {
+            assert!(*given == 0 || given == value);
+        }
+    }
+
+    #[test]
+    fn small_grid() {
+        let shape = Shape::new(2, 3).unwrap();
+        let mut gen = Generator::new_random(shape).unwrap();
+        while gen.eliminate_cage().unwrap() {}
+        let serialized_puzzle = gen.serialize();
+        assert_eq!(serialized_puzzle.shape, shape);
+        assert_eq!(serialized_puzzle.cell_values.len(), 36);
+        let puzzle = Puzzle::from_serializable(serialized_puzzle).unwrap();
+        assert_eq!(puzzle.solve(&SolverConfig::default()).unwrap().len(), 1);
+    }
 }
@@ -10,7 +10,9 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize)]
+use crate::ks::board::Shape;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SerializableCage {
     pub sum: usize,
     pub cell_indices: Vec<usize>,
@@ -20,6 +22,48 @@ pub struct SerializableCage {
 pub struct SerializablePuzzle {
     pub cell_values: Vec<usize>,
     pub cages: Vec<SerializableCage>,
+    /// Values placed before solving, with 0 for an empty cell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub givens: Option<Vec<usize>>,
+    /// The dimensions of the boxes, if they are not 3x3
+    #[serde(default, skip_serializing_if = "Shape::is_classic")]
+    pub shape: Shape,
+}
+
+/// The state of a puzzle part way through solving
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SerializableSnapshot {
+    /// The values still possible in each cell
+    pub candidates: Vec<Vec<usize>>,
+    /// The houses and the cages, including those split or derived while solving
+    pub cages: Vec<SerializableCage>,
+    /// Cages whose values need not be distinct, such as those derived from unions of houses
+    pub repeating_cages: Vec<SerializableCage>,
+    /// The dimensions of the boxes, if they are not 3x3
+    #[serde(default, skip_serializing_if = "Shape::is_classic")]
+    pub shape: Shape,
+}
+
+#[allow(unused)]
+impl SerializableSnapshot {
+    pub fn from_json_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    pub fn from_str(data: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(data)?)
+    }
+
+    pub fn to_json_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        Ok(serde_json::to_writer_pretty(
+            BufWriter::new(File::create(path)?),
+            self,
+        )?)
+    }
+
+    pub fn to_str(&self) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string(self)?)
+    }
 }
 
 #[allow(unused)]
@@ -58,20 +102,20 @@ impl SerializablePuzzle {
         const CELL_SIZE: u32 = 100;
         const MARGIN: u32 = 80;
         const HEADER_HEIGHT: u32 = 160;
-        const TOTAL_WIDTH: u32 = CELL_SIZE * 9 + MARGIN * 2;
-        const TOTAL_HEIGHT: u32 = CELL_SIZE * 9 + HEADER_HEIGHT + MARGIN;
+        let size = self.shape.size();
+        let total_width = CELL_SIZE * size as u32 + MARGIN * 2;
+        let total_height = CELL_SIZE * size as u32 + HEADER_HEIGHT + MARGIN;
 
         /* Calculate cage adjacency */
-        let cage_indices_by_cell =
-            self.cages
-                .iter()
-                .enumerate()
-                .fold([0; 81], |mut accum, (cage_index, cage)| {
-                    for cell_index in &cage.cell_indices {
-                        accum[*cell_index] = cage_index;
-                    }
-                    accum
-                });
+        let cage_indices_by_cell = self.cages.iter().enumerate().fold(
+            vec![0; self.shape.num_cells()],
+            |mut accum, (cage_index, cage)| {
+                for cell_index in &cage.cell_indices {
+                    accum[*cell_index] = cage_index;
+                }
+                accum
+            },
+        );
         let mut interference_map: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); self.cages.len()];
         let mut insert_interference = |a: usize, b: usize| {
             let cage_a = cage_indices_by_cell[a];
@@ -81,16 +125,16 @@ impl SerializablePuzzle {
                 interference_map[cage_b].insert(cage_a);
             }
         };
-        for row in 0..9 {
-            for col in 0..8 {
-                insert_interference(row * 9 + col, row * 9 + col + 1);
-                insert_interference(col * 9 + row, (col + 1) * 9 + row);
+        for row in 0..size {
+            for col in 0..(size - 1) {
+                insert_interference(row * size + col, row * size + col + 1);
+                insert_interference(col * size + row, (col + 1) * size + row);
             }
         }
-        for row in 0..8 {
-            for col in 0..8 {
-                insert_interference(row * 9 + col, (row + 1) * 9 + col + 1);
-                insert_interference((row + 1) * 9 + col, row * 9 + col + 1);
+        for row in 0..(size - 1) {
+            for col in 0..(size - 1) {
+                insert_interference(row * size + col, (row + 1) * size + col + 1);
+                insert_interference((row + 1) * size + col, row * size + col + 1);
             }
         }
         let mut interference_map = interference_map.into_iter().collect::<Vec<_>>();
@@ -152,15 +196,15 @@ impl SerializablePuzzle {
         let background = Rectangle::new()
             .set("x", 0)
             .set("y", 0)
-            .set("width", TOTAL_WIDTH)
-            .set("height", TOTAL_HEIGHT)
+            .set("width", total_width)
+            .set("height", total_height)
             .set("stroke", "none")
             .set("fill", "white");
 
         /* Generate colored squares */
         let mut squares = vec![];
-        for row in 0..9 {
-            for col in 0..9 {
+        for row in 0..size as u32 {
+            for col in 0..size as u32 {
                 squares.push(
                     Rectangle::new()
                         .set("x", MARGIN + CELL_SIZE * row)
@@ -170,14 +214,37 @@ impl SerializablePuzzle {
                         .set("stroke", "none")
                         .set(
                             "fill",
-                            COLORS[remapped_colors
-                                [&color_map[cage_indices_by_cell[(row * 9 + col) as usize]]]],
+                            COLORS[remapped_colors[&color_map
+                                [cage_indices_by_cell[row as usize * size + col as usize]]]],
                         ),
                 );
             }
         }
         let puzzle_group = squares.into_iter().fold(Group::new(), |g, s| g.add(s));
 
+        /* Generate givens */
+        let givens_group = self
+            .givens
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter(|(_, value)| **value != 0)
+            .map(|(cell_index, value)| {
+                Text::new()
+                    .set("text-anchor", "middle")
+                    .set(
+                        "x",
+                        MARGIN + CELL_SIZE * (cell_index / size) as u32 + CELL_SIZE / 2,
+                    )
+                    .set(
+                        "y",
+                        HEADER_HEIGHT + CELL_SIZE * (cell_index % size) as u32 + 70,
+                    )
+                    .set("font-size", 56)
+                    .add(TextNode::new(format!("{}", value)))
+            })
+            .fold(Group::new(), |g, t| g.add(t));
+
         /* Generate cage indices */
         let text_group = self
             .cages
@@ -187,14 +254,14 @@ impl SerializablePuzzle {
                 let col = cage
                     .cell_indices
                     .iter()
-                    .map(|cell_index| *cell_index % 9)
+                    .map(|cell_index| *cell_index % size)
                     .min()
                     .unwrap();
                 let row = cage
                     .cell_indices
                     .iter()
-                    .filter(|cell_index| *cell_index % 9 == col)
-                    .map(|cell_index| *cell_index / 9)
+                    .filter(|cell_index| *cell_index % size == col)
+                    .map(|cell_index| *cell_index / size)
                     .min()
                     .unwrap();
                 Text::new()
@@ -206,53 +273,59 @@ impl SerializablePuzzle {
             .fold(Group::new(), |g, t| g.add(t));
 
         /* Generate lines */
-        let horizontal_line_group = (0..=9)
+        let horizontal_line_group = (0..=size)
             .map(|i| {
-                let y = HEADER_HEIGHT + CELL_SIZE * i;
+                let y = HEADER_HEIGHT + CELL_SIZE * i as u32;
                 Line::new()
                     .set("x1", MARGIN - 2)
-                    .set("x2", TOTAL_WIDTH - MARGIN + 2)
+                    .set("x2", total_width - MARGIN + 2)
                     .set("y1", y)
                     .set("y2", y)
                     .set("stroke", "black")
-                    .set("stroke-width", if i % 3 == 0 { 4 } else { 1 })
+                    .set(
+                        "stroke-width",
+                        if i % self.shape.box_cols == 0 { 4 } else { 1 },
+                    )
             })
             .fold(Group::new(), |g, l| g.add(l));
-        let vertical_line_group = (0..=9)
+        let vertical_line_group = (0..=size)
             .map(|i| {
-                let x = MARGIN + CELL_SIZE * i;
+                let x = MARGIN + CELL_SIZE * i as u32;
                 Line::new()
                     .set("y1", HEADER_HEIGHT - 2)
-                    .set("y2", TOTAL_HEIGHT - MARGIN + 2)
+                    .set("y2", total_height - MARGIN + 2)
                     .set("x1", x)
                     .set("x2", x)
                     .set("stroke", "black")
-                    .set("stroke-width", if i % 3 == 0 { 4 } else { 1 })
+                    .set(
+                        "stroke-width",
+                        if i % self.shape.box_rows == 0 { 4 } else { 1 },
+                    )
             })
             .fold(Group::new(), |g, l| g.add(l));
 
         /* Generate titles */
         let title = Text::new()
             .set("text-anchor", "middle")
-            .set("x", TOTAL_WIDTH / 2)
+            .set("x", total_width / 2)
             .set("y", HEADER_HEIGHT - 48)
             .set("font-size", 60)
             .add(TextNode::new(title));
         let subtitle = Text::new()
             .set("text-anchor", "middle")
-            .set("x", TOTAL_WIDTH / 2)
+            .set("x", total_width / 2)
             .set("y", HEADER_HEIGHT - 16)
             .set("font-size", 24)
             .add(TextNode::new(format!("{} Cages", self.cages.len())));
         let date = Text::new()
             .set("x", MARGIN)
-            .set("y", TOTAL_HEIGHT - MARGIN + 24)
+            .set("y", total_height - MARGIN + 24)
             .set("font-size", 20)
             .add(TextNode::new(Local::now().format("%B %e, %Y").to_string()));
         let copyright = Text::new()
             .set("text-anchor", "end")
-            .set("x", TOTAL_WIDTH - MARGIN)
-            .set("y", TOTAL_HEIGHT - MARGIN + 24)
+            .set("x", total_width - MARGIN)
+            .set("y", total_height - MARGIN + 24)
             .set("font-size", 20)
             .add(TextNode::new(format!(
                 "Copyright {} by Daniel Winkelman.",
@@ -260,10 +333,11 @@ impl SerializablePuzzle {
             )));
 
         let document = Document::new()
-            .set("viewBox", (0, 0, TOTAL_WIDTH, TOTAL_HEIGHT))
+            .set("viewBox", (0, 0, total_width, total_height))
             .add(background)
             .add(puzzle_group)
             .add(text_group)
+            .add(givens_group)
             .add(horizontal_line_group)
             .add(vertical_line_group)
             .add(title)
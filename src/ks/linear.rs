@@ -0,0 +1,346 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::collections::BTreeSet;
+
+use crate::ks::{board::Board, cage::Cage, cancel::CancellationToken, error::SolverError};
+
+/// The most subsets of rows searched for implied cages
+const MAX_SUBSETS: usize = 100_000;
+
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// A cage sum written as an equation over the unsolved cells
+#[derive(Clone)]
+struct Equation {
+    coefficients: Vec<i128>,
+    sum: i128,
+}
+
+impl Equation {
+    fn is_zero(&self) -> bool {
+        self.coefficients
+            .iter()
+            .all(|coefficient| *coefficient == 0)
+    }
+
+    /// Divide through by the common factor so that coefficients stay small
+    fn normalize(&mut self) {
+        let divisor = self
+            .coefficients
+            .iter()
+            .fold(self.sum, |accum, coefficient| gcd(accum, *coefficient));
+        if divisor > 1 {
+            self.coefficients
+                .iter_mut()
+                .for_each(|coefficient| *coefficient /= divisor);
+            self.sum /= divisor;
+        }
+    }
+
+    /// Eliminate the pivot column from self using the pivot equation; None on overflow
+    fn eliminate(&mut self, pivot: &Equation, column: usize) -> Option<()> {
+        let scale_self = pivot.coefficients[column];
+        let scale_pivot = self.coefficients[column];
+        for (coefficient, pivot_coefficient) in
+            self.coefficients.iter_mut().zip(&pivot.coefficients)
+        {
+            *coefficient = coefficient
+                .checked_mul(scale_self)?
+                .checked_sub(pivot_coefficient.checked_mul(scale_pivot)?)?;
+        }
+        self.sum = self
+            .sum
+            .checked_mul(scale_self)?
+            .checked_sub(pivot.sum.checked_mul(scale_pivot)?)?;
+        self.normalize();
+        Some(())
+    }
+}
+
+/// Treat every cage sum as a linear equation over the cell values, and find the sets of up to
+/// max_cage_size cells whose sums are implied by combinations of the rows of the reduced system
+pub fn get_implied_cages(
+    cages: &BTreeSet<Cage>,
+    board: &Board,
+    max_cage_size: usize,
+    cancellation: &CancellationToken,
+) -> Result<Vec<Cage>, SolverError> {
+    /* Solved cells are moved to the right hand side */
+    let unsolved_cells = (0..board.len())
+        .filter(|cell_index| board[*cell_index].get_solution().is_none())
+        .collect::<Vec<usize>>();
+    let mut column_by_cell = vec![None; board.len()];
+    unsolved_cells
+        .iter()
+        .enumerate()
+        .for_each(|(column, cell_index)| column_by_cell[*cell_index] = Some(column));
+    let mut equations = cages
+        .iter()
+        .map(|cage| {
+            let mut equation = Equation {
+                coefficients: vec![0; unsolved_cells.len()],
+                sum: cage.sum as i128,
+            };
+            for cell_index in cage.cells.iter() {
+                match (
+                    column_by_cell[*cell_index],
+                    board[*cell_index].get_solution(),
+                ) {
+                    (Some(column), _) => equation.coefficients[column] += 1,
+                    (None, Some(value)) => equation.sum -= value as i128,
+                    (None, None) => panic!("Invalid condition"),
+                }
+            }
+            equation
+        })
+        .filter(|equation| !equation.is_zero() || equation.sum != 0)
+        .collect::<Vec<Equation>>();
+
+    /* Fraction-free Gauss-Jordan elimination */
+    let mut pivots = vec![];
+    for column in 0..unsolved_cells.len() {
+        let rank = pivots.len();
+        if let Some(pivot_row) =
+            (rank..equations.len()).find(|row| equations[*row].coefficients[column] != 0)
+        {
+            equations.swap(rank, pivot_row);
+            if equations[rank].coefficients[column] < 0 {
+                let pivot = &mut equations[rank];
+                pivot
+                    .coefficients
+                    .iter_mut()
+                    .for_each(|coefficient| *coefficient = -*coefficient);
+                pivot.sum = -pivot.sum;
+            }
+            let pivot = equations[rank].clone();
+            let mut overflowed_rows = vec![];
+            for (row, equation) in equations.iter_mut().enumerate() {
+                if row != rank
+                    && equation.coefficients[column] != 0
+                    && equation.eliminate(&pivot, column).is_none()
+                {
+                    overflowed_rows.push(row);
+                }
+            }
+            pivots.push(column);
+            /* Leaving out an equation rather than risk an unsound deduction can only leave out
+             * cages, and the pivot of each row stays at the same index as the row */
+            for row in overflowed_rows.into_iter().rev() {
+                equations.remove(row);
+                if row < pivots.len() {
+                    pivots.remove(row);
+                }
+            }
+        }
+    }
+    if equations[pivots.len()..]
+        .iter()
+        .any(|equation| equation.sum != 0)
+    {
+        /* The cage sums are inconsistent */
+        return Err(SolverError::contradiction());
+    }
+    equations.truncate(pivots.len());
+
+    /* A combination of rows is a cage if every coefficient is zero or one */
+    let mut output = vec![];
+    let mut try_cage = |combination: &Equation, scale: i128| {
+        let cells = combination
+            .coefficients
+            .iter()
+            .enumerate()
+            .filter(|(_, coefficient)| **coefficient != 0)
+            .map(|(column, coefficient)| (*coefficient == scale).then_some(unsolved_cells[column]))
+            .collect::<Option<Vec<usize>>>();
+        match cells {
+            Some(cells) if cells.len() <= max_cage_size => {
+                let sum = combination.sum;
+                if sum % scale == 0 && sum / scale > 0 {
+                    output.push(Cage::new(
+                        &board.shape(),
+                        cells,
+                        (sum / scale) as usize,
+                        false,
+                    ));
+                    Ok(())
+                } else {
+                    Err(SolverError::contradiction())
+                }
+            }
+            _ => Ok(()),
+        }
+    };
+
+    /* A coefficient can only be changed by the rows after the last row which touches it */
+    let mut last_row_by_column = vec![None; unsolved_cells.len()];
+    for (row, equation) in equations.iter().enumerate() {
+        for (column, coefficient) in equation.coefficients.iter().enumerate() {
+            if *coefficient != 0 {
+                last_row_by_column[column] = Some(row);
+            }
+        }
+    }
+    let is_pivot = {
+        let mut is_pivot = vec![false; unsolved_cells.len()];
+        pivots.iter().for_each(|column| is_pivot[*column] = true);
+        is_pivot
+    };
+
+    /* Rows must be linked by the non-pivot cells they share */
+    let is_connected = |rows: &[usize]| {
+        let mut connected = vec![rows[0]];
+        let mut remaining = rows[1..].to_vec();
+        while let Some(position) = remaining.iter().position(|row| {
+            connected.iter().any(|other_row| {
+                (0..unsolved_cells.len()).any(|column| {
+                    !is_pivot[column]
+                        && equations[*row].coefficients[column] != 0
+                        && equations[*other_row].coefficients[column] != 0
+                })
+            })
+        }) {
+            connected.push(remaining.swap_remove(position));
+        }
+        remaining.is_empty()
+    };
+
+    /* Depth-first search over subsets of rows in order, with the combination of the rows so far
+     * scaled so that every pivot is equal to the scale */
+    let mut stack = vec![(
+        vec![],
+        Equation {
+            coefficients: vec![0; unsolved_cells.len()],
+            sum: 0,
+        },
+        1i128,
+    )];
+    let mut num_subsets = 0;
+    while let Some((rows, combination, scale)) = stack.pop() {
+        cancellation.check()?;
+        /* The cages found so far are sound, even if the search is cut short */
+        num_subsets += 1;
+        if num_subsets > MAX_SUBSETS {
+            break;
+        }
+        let next_row = rows.last().map_or(0, |row| row + 1);
+        for (row, equation) in equations.iter().enumerate().skip(next_row) {
+            /* Give up on the subset rather than risk an unsound deduction */
+            let row_scale = equation.coefficients[pivots[row]];
+            let Some(new_scale) = (scale / gcd(scale, row_scale)).checked_mul(row_scale) else {
+                continue;
+            };
+            let (factor, row_factor) = (new_scale / scale, new_scale / row_scale);
+            let combine = |a: i128, b: i128| {
+                a.checked_mul(factor)?
+                    .checked_add(b.checked_mul(row_factor)?)
+            };
+            let Some(coefficients) = combination
+                .coefficients
+                .iter()
+                .zip(&equation.coefficients)
+                .map(|(a, b)| combine(*a, *b))
+                .collect::<Option<Vec<i128>>>()
+            else {
+                continue;
+            };
+            let Some(sum) = combine(combination.sum, equation.sum) else {
+                continue;
+            };
+            let new_combination = Equation { coefficients, sum };
+
+            /* Coefficients which no later row can change must already be zero or one */
+            let mut num_fixed_cells = 0;
+            let feasible = new_combination
+                .coefficients
+                .iter()
+                .zip(&last_row_by_column)
+                .all(|(coefficient, last_row)| {
+                    if last_row.is_some_and(|last_row| last_row > row) {
+                        true
+                    } else {
+                        num_fixed_cells += (*coefficient != 0) as usize;
+                        *coefficient == 0 || *coefficient == new_scale
+                    }
+                });
+            if !feasible || num_fixed_cells > max_cage_size {
+                continue;
+            }
+            let mut new_rows = rows.clone();
+            new_rows.push(row);
+            if is_connected(&new_rows) {
+                try_cage(&new_combination, new_scale)?;
+            }
+            if new_rows.len() < max_cage_size {
+                stack.push((new_rows, new_combination, new_scale));
+            }
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_implied_cages() {
+        let cages = BTreeSet::from([
+            Cage::from_parts(vec![0, 1], 3, true),
+            Cage::from_parts(vec![1, 2], 5, true),
+            Cage::from_parts(vec![0, 1, 2], 6, true),
+            Cage::from_parts(vec![3, 4, 5], 15, true),
+        ]);
+        let implied_cages =
+            get_implied_cages(&cages, &Board::default(), 4, &CancellationToken::default()).unwrap();
+        let shape = Board::default().shape();
+        assert!(implied_cages.contains(&Cage::new(&shape, vec![0], 1, false)));
+        assert!(implied_cages.contains(&Cage::new(&shape, vec![1], 2, false)));
+        assert!(implied_cages.contains(&Cage::new(&shape, vec![2], 3, false)));
+        assert!(implied_cages.contains(&Cage::new(&shape, vec![3, 4, 5], 15, false)));
+    }
+
+    #[test]
+    fn test_three_row_combination() {
+        /* [0, 1, 3] + [0, 2, 4] - [0, 3, 4] = [0, 1, 2] */
+        let cages = BTreeSet::from([
+            Cage::from_parts(vec![0, 1, 3], 7, true),
+            Cage::from_parts(vec![0, 2, 4], 9, true),
+            Cage::from_parts(vec![0, 3, 4], 10, true),
+        ]);
+        let implied_cages =
+            get_implied_cages(&cages, &Board::default(), 4, &CancellationToken::default()).unwrap();
+        assert!(implied_cages.contains(&Cage::from_parts(vec![0, 1, 2], 6, true)));
+    }
+
+    #[test]
+    fn test_inconsistent_sums() {
+        let cages = BTreeSet::from([
+            Cage::from_parts(vec![0, 1], 3, true),
+            Cage::from_parts(vec![1, 2], 5, true),
+            Cage::from_parts(vec![0, 2], 5, true),
+        ]);
+        assert!(
+            get_implied_cages(&cages, &Board::default(), 4, &CancellationToken::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let cages = BTreeSet::from([
+            Cage::from_parts(vec![0, 1], 3, true),
+            Cage::from_parts(vec![1, 2], 5, true),
+        ]);
+        let cancellation = CancellationToken::default();
+        cancellation.cancel();
+        assert!(matches!(
+            get_implied_cages(&cages, &Board::default(), 4, &cancellation),
+            Err(SolverError::Cancelled(_))
+        ));
+    }
+}
@@ -1,9 +1,24 @@
 // Copyright 2022 by Daniel Winkelman. All rights reserved.
 
+pub mod als;
+pub mod board;
 pub mod cage;
+pub mod cancel;
 pub mod cell;
+pub mod chains;
+pub mod cnf;
 pub mod combinations;
+pub mod config;
+pub mod dlx;
+pub mod error;
+pub mod fish;
 pub mod generator;
 pub mod io;
+pub mod linear;
+pub mod observer;
+pub mod pool;
 pub mod puzzle;
+pub mod rating;
+pub mod step;
 pub mod util;
+pub mod validation;
@@ -0,0 +1,101 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::{collections::BTreeSet, fmt::Debug};
+
+use crate::ks::{cage::Cage, step::Step};
+
+/// Receives events from the solver and the generator, possibly from several worker threads at
+/// once; every event is ignored unless overridden
+pub trait Observer: Send + Sync {
+    /// A technique eliminated candidates or split a cage
+    fn on_step(&self, _step: &Step) {}
+
+    /// A cage was split into a cage with a known combination and the cells remaining
+    fn on_partition(&self, _cage: &Cage, _new_cage: &Cage, _remaining_cage: &Cage) {}
+
+    /// A value was guessed for a cell, nested inside depth - 1 other guesses
+    fn on_guess(&self, _cell_index: usize, _value: usize, _depth: usize) {}
+
+    /// A guessed value led to a contradiction
+    fn on_backtrack(&self, _cell_index: usize, _value: usize, _depth: usize) {}
+
+    /// Two cages were merged, or can never be merged because they share a value
+    fn on_merge(&self, _a: &BTreeSet<usize>, _b: &BTreeSet<usize>, _accepted: bool) {}
+
+    /// A merge was kept only if the puzzle still had a unique solution
+    fn on_uniqueness_check(&self, _num_cages: usize, _unique: bool) {}
+}
+
+impl Debug for dyn Observer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Observer")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::ks::{
+        board::Shape,
+        config::SolverConfig,
+        generator::Generator,
+        puzzle::{Backend, Puzzle},
+    };
+
+    #[derive(Default)]
+    struct Counter {
+        events: Mutex<Vec<&'static str>>,
+    }
+
+    impl Counter {
+        fn count(&self, event: &str) -> usize {
+            let events = self.events.lock().unwrap();
+            events.iter().filter(|other| **other == event).count()
+        }
+    }
+
+    impl Observer for Counter {
+        fn on_step(&self, _step: &Step) {
+            self.events.lock().unwrap().push("step");
+        }
+
+        fn on_guess(&self, _cell_index: usize, _value: usize, depth: usize) {
+            assert!(depth > 0);
+            self.events.lock().unwrap().push("guess");
+        }
+
+        fn on_merge(&self, a: &BTreeSet<usize>, b: &BTreeSet<usize>, _accepted: bool) {
+            assert!(a.is_disjoint(b));
+            self.events.lock().unwrap().push("merge");
+        }
+
+        fn on_uniqueness_check(&self, _num_cages: usize, _unique: bool) {
+            self.events.lock().unwrap().push("uniqueness");
+        }
+    }
+
+    #[test]
+    fn observe_events() {
+        let counter = Arc::new(Counter::default());
+        let config = SolverConfig {
+            observer: Some(counter.clone()),
+            max_guess_depth: 81,
+            ..SolverConfig::default()
+        };
+
+        /* An empty board needs guessing */
+        let mut puzzle = Puzzle::new();
+        puzzle.enable_trace();
+        let solutions = puzzle.solve_with_limit(Backend::Logic, &config, 1).unwrap();
+        assert!(counter.count("step") >= solutions[0].get_trace().unwrap().len());
+        assert!(counter.count("guess") > 0);
+
+        let mut gen = Generator::new_canonical(Shape::default());
+        gen.set_config(config);
+        assert!(gen.eliminate_cage().unwrap());
+        assert!(counter.count("merge") >= 1);
+        assert!(counter.count("uniqueness") >= 1);
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    thread,
+};
+
+/// The result of expanding a node of a search tree
+pub enum Expansion<T, S> {
+    Branch(Vec<T>),
+    /// A solution, or a dead end if there is none
+    Leaf(Option<S>),
+}
+
+/// Nodes are identified by the indices of the branches taken from the root, so that ordering
+/// paths gives the order of a sequential depth-first search
+struct Task<T> {
+    path: Vec<usize>,
+    node: T,
+}
+
+pub fn get_default_num_threads() -> usize {
+    thread::available_parallelism().map_or(1, |num_threads| num_threads.get())
+}
+
+/// Search a tree on a pool of workers, each of which works depth-first on its own queue and steals
+/// the shallowest work from the other queues when its own is empty. The result is the same as a
+/// sequential depth-first search which stops at the first error or once limit solutions have been
+/// found, whatever the number of threads. No threads are started unless the root branches.
+pub fn search<T, S, E, F>(root: T, num_threads: usize, limit: usize, expand: F) -> Result<Vec<S>, E>
+where
+    T: Send,
+    S: Send,
+    E: Send,
+    F: Fn(T) -> Result<Expansion<T, S>, E> + Sync,
+{
+    if limit == 0 {
+        return Ok(vec![]);
+    }
+    let children = match expand(root)? {
+        Expansion::Branch(children) => children,
+        Expansion::Leaf(solution) => return Ok(solution.into_iter().collect()),
+    };
+
+    let num_threads = num_threads.max(1);
+    let queues = (0..num_threads)
+        .map(|_| Mutex::new(VecDeque::new()))
+        .collect::<Vec<Mutex<VecDeque<Task<T>>>>>();
+    let num_pending = AtomicUsize::new(0);
+    /* Idle workers wait for this to change, which it does whenever there is new work or none is
+     * left, so that no wakeup is missed between looking for work and waiting */
+    let num_wakeups = Mutex::new(0usize);
+    let wakeup = Condvar::new();
+    let notify = || {
+        *num_wakeups.lock().unwrap() += 1;
+        wakeup.notify_all();
+    };
+    let push_children = |worker_index: usize, path: &[usize], children: Vec<T>| {
+        num_pending.fetch_add(children.len(), Ordering::SeqCst);
+        let mut queue = queues[worker_index].lock().unwrap();
+        for (i, node) in children.into_iter().enumerate().rev() {
+            let mut path = path.to_vec();
+            path.push(i);
+            queue.push_back(Task { path, node });
+        }
+    };
+    push_children(0, &[], children);
+    let results = Mutex::new(BTreeMap::<Vec<usize>, Result<S, E>>::new());
+
+    /* Nothing after the first error or the last solution needed can change the result */
+    let get_cutoff = |results: &BTreeMap<Vec<usize>, Result<S, E>>| {
+        let mut num_solutions = 0;
+        for (path, result) in results.iter() {
+            num_solutions += result.is_ok() as usize;
+            if result.is_err() || num_solutions >= limit {
+                return Some(path.clone());
+            }
+        }
+        None
+    };
+
+    let work = |worker_index: usize| loop {
+        let seen_wakeups = *num_wakeups.lock().unwrap();
+        /* Only one queue is locked at a time */
+        let own_task = queues[worker_index].lock().unwrap().pop_back();
+        let task = own_task.or_else(|| {
+            (1..num_threads)
+                .map(|offset| (worker_index + offset) % num_threads)
+                .find_map(|other_index| queues[other_index].lock().unwrap().pop_front())
+        });
+        let task = match task {
+            Some(task) => task,
+            None if num_pending.load(Ordering::SeqCst) == 0 => break,
+            None => {
+                drop(
+                    wakeup
+                        .wait_while(num_wakeups.lock().unwrap(), |num_wakeups| {
+                            *num_wakeups == seen_wakeups && num_pending.load(Ordering::SeqCst) != 0
+                        })
+                        .unwrap(),
+                );
+                continue;
+            }
+        };
+        let cutoff = get_cutoff(&results.lock().unwrap());
+        if cutoff.is_none_or(|cutoff| task.path < cutoff) {
+            match expand(task.node) {
+                Ok(Expansion::Branch(children)) => {
+                    push_children(worker_index, &task.path, children);
+                    notify();
+                }
+                Ok(Expansion::Leaf(Some(solution))) => {
+                    results.lock().unwrap().insert(task.path, Ok(solution));
+                }
+                Ok(Expansion::Leaf(None)) => {}
+                Err(error) => {
+                    results.lock().unwrap().insert(task.path, Err(error));
+                }
+            }
+        }
+        if num_pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            notify();
+        }
+    };
+    thread::scope(|scope| {
+        for worker_index in 0..num_threads {
+            let work = &work;
+            scope.spawn(move || work(worker_index));
+        }
+    });
+
+    let mut output = vec![];
+    for (_, result) in results.into_inner().unwrap() {
+        if output.len() >= limit {
+            break;
+        }
+        output.push(result?);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Binary strings of the given length, with an error at the given string
+    fn search_strings(
+        num_threads: usize,
+        limit: usize,
+        error_at: Option<&'static str>,
+    ) -> Result<Vec<String>, String> {
+        search(String::new(), num_threads, limit, |node: String| {
+            if Some(node.as_str()) == error_at {
+                Err(node)
+            } else if node.len() == 4 {
+                Ok(Expansion::Leaf(node.ends_with('1').then_some(node)))
+            } else {
+                Ok(Expansion::Branch(vec![
+                    format!("{}0", node),
+                    format!("{}1", node),
+                ]))
+            }
+        })
+    }
+
+    #[test]
+    fn deterministic_order() {
+        let expected = (0..16)
+            .filter(|i| i % 2 == 1)
+            .map(|i| format!("{:04b}", i))
+            .collect::<Vec<String>>();
+        for num_threads in [1, 2, 8] {
+            assert_eq!(
+                search_strings(num_threads, usize::MAX, None),
+                Ok(expected.clone())
+            );
+            assert_eq!(
+                search_strings(num_threads, 3, None),
+                Ok(expected[..3].to_vec())
+            );
+            assert_eq!(
+                search_strings(num_threads, usize::MAX, Some("10")),
+                Err("10".to_string())
+            );
+            assert_eq!(
+                search_strings(num_threads, 4, Some("10")),
+                Ok(expected[..4].to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn root_without_threads() {
+        /* A root which does not branch is expanded on the calling thread */
+        let caller = thread::current().id();
+        let expand_root = |solution: Option<usize>| {
+            search(solution, 8, usize::MAX, |node: Option<usize>| {
+                assert_eq!(thread::current().id(), caller);
+                Ok::<_, ()>(Expansion::<Option<usize>, usize>::Leaf(node))
+            })
+        };
+        assert_eq!(expand_root(Some(1)), Ok(vec![1]));
+        assert_eq!(expand_root(None), Ok(vec![]));
+        assert_eq!(search_strings(8, usize::MAX, Some("")), Err(String::new()));
+        assert_eq!(search_strings(8, 0, None), Ok(vec![]));
+    }
+}
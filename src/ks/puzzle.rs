@@ -1,53 +1,154 @@
 // Copyright 2022 by Daniel Winkelman. All rights reserved.
 
 use crate::ks::{
-    cage::Cage, cell::Cell, io::SerializablePuzzle, util::get_population_distribution,
+    als::{
+        get_almost_locked_sets, restrict_by_als_xy_wing, restrict_by_als_xz,
+        restrict_by_death_blossom,
+    },
+    board::{Board, Shape},
+    cage::Cage,
+    cell::Cell,
+    chains::{
+        restrict_by_aic, restrict_by_simple_colouring, restrict_by_x_chain, restrict_by_xy_wing,
+        restrict_by_xyz_wing, LinkGraph,
+    },
+    cnf::{apply_model, Cnf},
+    combinations::PossibleValues,
+    config::SolverConfig,
+    dlx::solve_board_by_exact_cover,
+    error::{Location, SolverError},
+    fish::restrict_by_fish,
+    io::{SerializableCage, SerializablePuzzle, SerializableSnapshot},
+    linear::get_implied_cages,
+    pool::{search, Expansion},
+    step::{Placement, Step, Technique},
+    util::get_population_distribution,
 };
 use std::{
     collections::{BTreeMap, BTreeSet},
     fmt::Display,
-    thread,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
 };
 
+type Reduction = fn(&mut Puzzle) -> Result<bool, SolverError>;
+type CageRestriction = fn(&Cage, &mut Board) -> Result<bool, SolverError>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Logical techniques, guessing only when they get stuck
+    Logic,
+    /// Brute force search with Dancing Links, independent of the logical techniques
+    ExactCover,
+}
+
+/// The cages, and for each cell the indices of the cages which contain it
+struct CageIndex {
+    cages: Vec<Cage>,
+    cell_cages: Vec<Vec<usize>>,
+}
+
 #[derive(Clone)]
 pub struct Puzzle {
-    pub board: [Cell; 81],
+    pub board: Board,
     cages: BTreeSet<Cage>,
+    /// The houses and the cages as given, before any were derived or partitioned
+    given_cages: BTreeSet<Cage>,
+    trace: Option<Vec<Step>>,
+    config: SolverConfig,
+    /// Cages are derived from the houses once the limits of the solver are known
+    derivation_pending: bool,
+    /// Cages implied by the others rather than given, whose deductions are credited to deriving
+    /// them
+    derived_cages: BTreeSet<Cage>,
+    /// Built when a cell is first restricted, and cleared whenever the cages change
+    cage_index: Option<Arc<CageIndex>>,
 }
 
 impl Puzzle {
+    #[allow(unused)]
     pub fn new() -> Self {
-        let mut output = Self {
-            board: [Cell::default(); 81],
-            cages: BTreeSet::new(),
-        };
-        for i in 0..9 {
-            output
-                .cages
-                .insert(Cage::new(((i * 9)..((i + 1) * 9)).collect(), 45, true));
+        Self::with_shape(Shape::default())
+    }
+
+    pub fn with_shape(shape: Shape) -> Self {
+        Self {
+            board: Board::new(shape),
+            cages: Self::get_houses(&shape),
+            given_cages: Self::get_houses(&shape),
+            trace: None,
+            config: SolverConfig::default(),
+            derivation_pending: false,
+            derived_cages: BTreeSet::new(),
+            cage_index: None,
         }
-        for i in 0..9 {
-            output
-                .cages
-                .insert(Cage::new((0..9).map(|j| j * 9 + i).collect(), 45, true));
-        }
-        for i in 0..3 {
-            for j in 0..3 {
-                output.cages.insert(Cage::new(
-                    (0..3)
-                        .map(|ii| (0..3).map(move |jj| (i * 3 + ii) * 9 + (j * 3 + jj)))
-                        .flatten()
-                        .collect(),
-                    45,
-                    true,
-                ));
-            }
+    }
+
+    /// Keep a trace of every deduction made while solving
+    pub fn enable_trace(&mut self) {
+        self.trace = Some(vec![]);
+    }
+
+    /// Get the deductions made so far, if a trace is being kept
+    pub fn get_trace(&self) -> Option<&[Step]> {
+        self.trace.as_deref()
+    }
+
+    /// Whether deductions are recorded, which needs a copy of the board from before each one
+    fn is_recording(&self) -> bool {
+        self.trace.is_some() || self.config.observer.is_some()
+    }
+
+    /// Apply a technique to the board, recording a step with the cages it used if it made progress
+    fn apply_technique<F>(&mut self, technique: Technique, restrict: F) -> Result<bool, SolverError>
+    where
+        F: FnOnce(&mut Board, &mut BTreeSet<Cage>) -> Result<bool, SolverError>,
+    {
+        if !self.config.allows(technique) {
+            return Ok(false);
+        }
+        let init_board = self.is_recording().then(|| self.board.clone());
+        let mut cages = BTreeSet::new();
+        let progress = restrict(&mut self.board, &mut cages)?;
+        if let (true, Some(init_board)) = (progress, &init_board) {
+            Step::record(
+                &mut self.trace,
+                self.config.observer.as_deref(),
+                technique,
+                &cages.iter().collect::<Vec<&Cage>>(),
+                init_board,
+                &self.board,
+            );
+        }
+        Ok(progress)
+    }
+
+    /// Deductions made by a cage which was derived are credited to deriving it
+    fn get_cage_technique(
+        derived_cages: &BTreeSet<Cage>,
+        cage: &Cage,
+        technique: Technique,
+    ) -> Technique {
+        if derived_cages.contains(cage) {
+            Technique::DerivedCage
+        } else {
+            technique
         }
-        output
     }
 
-    pub fn from_serializable(s: SerializablePuzzle) -> Self {
-        let mut output = Puzzle::new();
+    /// Get the rows, columns, and boxes
+    pub fn get_houses(shape: &Shape) -> BTreeSet<Cage> {
+        shape
+            .get_houses()
+            .into_iter()
+            .map(|cells| Cage::from_parts(cells, shape.house_sum(), true))
+            .collect()
+    }
+
+    pub fn from_serializable(s: SerializablePuzzle) -> Result<Self, SolverError> {
+        let mut output = Puzzle::with_shape(s.shape);
         output.init_cages(
             s.cages
                 .into_iter()
@@ -55,76 +156,246 @@ impl Puzzle {
                 .collect(),
             true,
         );
+        if let Some(givens) = s.givens {
+            if givens.len() != output.board.len() {
+                return Err(SolverError::invalid_input());
+            }
+            for (cell_index, value) in givens.into_iter().enumerate() {
+                if value > output.board.shape().size() {
+                    return Err(SolverError::invalid_input().at_cell(cell_index));
+                }
+                if value != 0 {
+                    output.board[cell_index].restrict_to(1 << value)?;
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Save the candidates and the cages, so that solving can continue later
+    #[allow(unused)]
+    pub fn to_snapshot(&self) -> SerializableSnapshot {
+        let mut puzzle = self.clone();
+        /* If cancelled, the derived cages are left out, since they can be derived again */
+        let _ = puzzle.derive_cages();
+        let (cages, repeating_cages) = puzzle
+            .cages
+            .iter()
+            .partition::<Vec<&Cage>, _>(|cage| cage.uniqueness);
+        let serialize_cages = |cages: Vec<&Cage>| {
+            cages
+                .into_iter()
+                .map(|cage| SerializableCage {
+                    sum: cage.sum,
+                    cell_indices: cage.cells.clone(),
+                })
+                .collect()
+        };
+        SerializableSnapshot {
+            candidates: puzzle
+                .board
+                .iter()
+                .map(|cell| cell.possible_values().collect())
+                .collect(),
+            cages: serialize_cages(cages),
+            repeating_cages: serialize_cages(repeating_cages),
+            shape: puzzle.board.shape(),
+        }
+    }
+
+    /// Load candidates and cages, which may have come from a player's own pencil marks
+    #[allow(unused)]
+    pub fn from_snapshot(s: SerializableSnapshot) -> Result<Self, SolverError> {
+        let mut output = Puzzle::with_shape(s.shape);
+        if s.candidates.len() != output.board.len() {
+            return Err(SolverError::invalid_input());
+        }
+        for (cell_index, values) in s.candidates.iter().enumerate() {
+            let mut bits = 0;
+            for value in values {
+                if !(1..=s.shape.size()).contains(value) {
+                    return Err(SolverError::invalid_input().at_cell(cell_index));
+                }
+                bits |= 1 << value;
+            }
+            output.board[cell_index]
+                .restrict_to(bits)
+                .map_err(|error| error.at_cell(cell_index))?;
+        }
+        let mut cages = BTreeSet::new();
+        for (cage, uniqueness) in s
+            .cages
+            .into_iter()
+            .map(|cage| (cage, true))
+            .chain(s.repeating_cages.into_iter().map(|cage| (cage, false)))
+        {
+            let cage = Cage::new(&s.shape, cage.cell_indices, cage.sum, uniqueness);
+            if cage.cells.is_empty() || cage.cells.iter().any(|cell| *cell >= output.board.len()) {
+                return Err(SolverError::invalid_input().in_cage(&cage));
+            }
+            cages.insert(cage);
+        }
+        output.cage_index = None;
+        output.cages = cages;
+        /* Which cages were given is not saved, but every cage in a snapshot holds in every
+         * solution, so counting solutions with all of them gives the same count */
+        output.given_cages = output.cages.clone();
+        Ok(output)
+    }
+
+    /// Get the unions of two or three adjacent rows, columns, or boxes within a band or stack
+    fn get_house_unions(shape: &Shape) -> BTreeSet<Cage> {
+        let size = shape.size();
+        let (boxes_per_band, boxes_per_stack) = (size / shape.box_cols, size / shape.box_rows);
+        let mut output = BTreeSet::new();
+        for num_houses in 2..=3 {
+            let sum = shape.house_sum() * num_houses;
+            let starts = |num_lines: usize| 0..(num_lines + 1).saturating_sub(num_houses);
+            for start in starts(size) {
+                let houses = start..(start + num_houses);
+                output.insert(Cage::new(
+                    shape,
+                    houses
+                        .clone()
+                        .flat_map(|row| shape.row_cells(row))
+                        .collect(),
+                    sum,
+                    false,
+                ));
+                output.insert(Cage::new(
+                    shape,
+                    houses.flat_map(|col| shape.col_cells(col)).collect(),
+                    sum,
+                    false,
+                ));
+            }
+            for band in 0..boxes_per_stack {
+                for start in starts(boxes_per_band) {
+                    let houses = start..(start + num_houses);
+                    output.insert(Cage::new(
+                        shape,
+                        houses
+                            .flat_map(|j| shape.box_cells(band * boxes_per_band + j))
+                            .collect(),
+                        sum,
+                        false,
+                    ));
+                }
+            }
+            for stack in 0..boxes_per_band {
+                for start in starts(boxes_per_stack) {
+                    let houses = start..(start + num_houses);
+                    output.insert(Cage::new(
+                        shape,
+                        houses
+                            .flat_map(|i| shape.box_cells(i * boxes_per_band + stack))
+                            .collect(),
+                        sum,
+                        false,
+                    ));
+                }
+            }
+        }
         output
     }
 
-    fn derive_cages(&mut self) {
-        const MAX_DERIVED_CAGE_SIZE: usize = 8;
+    /// Add the cages implied by the houses and the unions of houses, if not already added
+    fn derive_cages(&mut self) -> Result<(), SolverError> {
+        if !self.derivation_pending || !self.config.allows(Technique::DerivedCage) {
+            return Ok(());
+        }
         /* For each top-level cage, see which other cages are completely contained or overlap */
-        let derive_cages = |parent_cage: &Cage| -> Vec<Cage> {
+        let shape = self.board.shape();
+        let derive_cages = |parent_cage: &Cage| -> Result<Vec<Cage>, SolverError> {
+            self.config.cancellation.check()?;
             let mut parent_cage = parent_cage.clone();
             let mut excess_cage = Cage::empty();
-            for child_cage in self.cages.iter().filter(|cage| cage.cells.len() < 9) {
+            for child_cage in self
+                .cages
+                .iter()
+                .filter(|cage| cage.cells.len() < shape.size())
+            {
                 let (intersection, parent_difference, child_difference) =
                     parent_cage.get_intersection_and_difference(child_cage);
-                if child_difference.len() == 0 {
+                if child_difference.is_empty() {
                     /* The child is contained within the parent */
-                    parent_cage =
-                        Cage::new(parent_difference, parent_cage.sum - child_cage.sum, true);
-                } else if intersection.len() > 0 {
+                    let sum = parent_cage.sum.checked_sub(child_cage.sum).ok_or_else(|| {
+                        SolverError::impossible_sum(parent_cage.cells.len(), parent_cage.sum)
+                            .in_cage(&parent_cage)
+                    })?;
+                    parent_cage = Cage::new(&shape, parent_difference, sum, parent_cage.uniqueness);
+                } else if !intersection.is_empty() {
                     /* The child at least partially overlaps the parent */
                     excess_cage = excess_cage.merge(child_cage);
                 }
             }
             let (_, excess_difference, parent_difference) =
                 excess_cage.get_intersection_and_difference(&parent_cage);
-            /* There should be no parent cells remaining not included in the excess cage */
-            assert_eq!(parent_difference.len(), 0);
+            /* Every parent cell must be in some child cage, unless the cages are inconsistent */
+            if !parent_difference.is_empty() {
+                return Err(SolverError::contradiction()
+                    .at_cell(parent_difference[0])
+                    .in_cage(&parent_cage));
+            }
             let mut output = vec![];
-            if 0 < excess_difference.len() && excess_difference.len() <= 4 {
+            if !excess_difference.is_empty()
+                && excess_difference.len() <= self.config.max_outie_size
+            {
                 /* There are cells that extend beyond the parent cage */
-                output.push(Cage::new(
-                    excess_difference,
-                    excess_cage.sum - parent_cage.sum,
-                    false,
-                ));
+                let sum = excess_cage
+                    .sum
+                    .checked_sub(parent_cage.sum)
+                    .ok_or_else(|| {
+                        SolverError::impossible_sum(excess_cage.cells.len(), excess_cage.sum)
+                            .in_cage(&excess_cage)
+                    })?;
+                output.push(Cage::new(&shape, excess_difference, sum, false));
             }
-            if 0 < parent_cage.cells.len() && parent_cage.cells.len() <= MAX_DERIVED_CAGE_SIZE {
+            if !parent_cage.cells.is_empty()
+                && parent_cage.cells.len() <= self.config.max_derived_cage_size
+            {
                 /* There are some cells leftover from the parent cage */
                 output.push(parent_cage);
             }
-            output
+            Ok(output)
         };
 
         let mut new_cages = self
             .cages
             .iter()
-            .filter(|cage| cage.cells.len() == 9)
-            .map(|cage| derive_cages(cage))
+            .filter(|cage| cage.cells.len() == shape.size())
+            .chain(Self::get_house_unions(&shape).iter())
+            .map(derive_cages)
+            .collect::<Result<Vec<Vec<Cage>>, SolverError>>()?
+            .into_iter()
             .flatten()
+            .filter(|cage| !self.cages.contains(cage))
             .collect::<BTreeSet<Cage>>();
-        let mut cage_len_count = BTreeMap::new();
-        for cage in new_cages.iter() {
-            *cage_len_count.entry(cage.cells.len()).or_insert(0) += 1;
-        }
+        self.derived_cages.extend(new_cages.iter().cloned());
         self.cages.append(&mut new_cages);
+        self.cage_index = None;
+        self.derivation_pending = false;
+        Ok(())
     }
 
     pub fn init_cages(&mut self, cages: Vec<(usize, Vec<usize>)>, perform_checks: bool) {
         for (sum, cells) in cages {
-            self.cages.insert(Cage::new(cells, sum, true));
+            let cage = Cage::from_parts(cells, sum, true);
+            self.given_cages.insert(cage.clone());
+            self.cages.insert(cage);
         }
+        self.cage_index = None;
         if perform_checks {
             let check = self.check_cages(4);
-            if check.len() > 0 {
+            if !check.is_empty() {
                 panic!("Cells in cages are not balanced: {:?}", check);
             }
-            self.derive_cages();
+            self.derivation_pending = true;
         }
     }
 
     fn check_cages(&self, expected: usize) -> Vec<(usize, usize)> {
-        let mut sums: [usize; 81] = [0; 81];
+        let mut sums = vec![0; self.board.len()];
         for cage in self.cages.iter() {
             for cell in cage.cells.iter() {
                 sums[*cell] += 1;
@@ -145,7 +416,7 @@ impl Puzzle {
     /// For each cell, take the size of the smallest cage of which it is a member, and aggregate
     #[allow(unused)]
     pub fn get_cell_solvability_distribution(&self) -> BTreeMap<usize, usize> {
-        let mut minimal_cage_size = vec![9; 81];
+        let mut minimal_cage_size = vec![self.board.shape().size(); self.board.len()];
         for cage in self.cages.iter() {
             for cell in cage.cells.iter() {
                 minimal_cage_size[*cell] = minimal_cage_size[*cell].min(cage.cells.len());
@@ -154,26 +425,131 @@ impl Puzzle {
         get_population_distribution(&mut minimal_cage_size.iter(), |x| *x)
     }
 
-    fn reduce_by_combination(&mut self) -> Result<bool, ()> {
+    fn reduce_by_uniform_combination(&mut self) -> Result<(), SolverError> {
+        if !self.config.allows(Technique::UniformCombination) {
+            return Ok(());
+        }
+        let recording = self.is_recording();
+        self.cages.iter().try_for_each(|cage| {
+            let init_board = recording.then(|| self.board.clone());
+            if let (true, Some(init_board)) = (
+                cage.restrict_by_uniform_combination(&mut self.board)
+                    .map_err(|error| error.in_cage(cage))?,
+                &init_board,
+            ) {
+                Step::record(
+                    &mut self.trace,
+                    self.config.observer.as_deref(),
+                    Self::get_cage_technique(
+                        &self.derived_cages,
+                        cage,
+                        Technique::UniformCombination,
+                    ),
+                    &[cage],
+                    init_board,
+                    &self.board,
+                );
+            }
+            Ok(())
+        })
+    }
+
+    fn reduce_by_bounds(&mut self) -> Result<bool, SolverError> {
+        if !self.config.allows(Technique::Bounds) {
+            return Ok(false);
+        }
+        let recording = self.is_recording();
         self.cages.iter().try_fold(false, |progress, cage| {
-            Ok(cage.restrict_by_combination(&mut self.board)? | progress)
+            let init_board = recording.then(|| self.board.clone());
+            if cage
+                .restrict_by_bounds(&mut self.board)
+                .map_err(|error| error.in_cage(cage))?
+            {
+                if let Some(init_board) = &init_board {
+                    Step::record(
+                        &mut self.trace,
+                        self.config.observer.as_deref(),
+                        Self::get_cage_technique(&self.derived_cages, cage, Technique::Bounds),
+                        &[cage],
+                        init_board,
+                        &self.board,
+                    );
+                }
+                Ok(true)
+            } else {
+                Ok(progress)
+            }
+        })
+    }
+
+    fn reduce_by_combination(&mut self) -> Result<bool, SolverError> {
+        if !self.config.allows(Technique::Combination) {
+            return Ok(false);
+        }
+        let recording = self.is_recording();
+        self.cages.iter().try_fold(false, |progress, cage| {
+            let init_board = recording.then(|| self.board.clone());
+            if cage
+                .restrict_by_combination(&mut self.board)
+                .map_err(|error| error.in_cage(cage))?
+            {
+                if let Some(init_board) = &init_board {
+                    Step::record(
+                        &mut self.trace,
+                        self.config.observer.as_deref(),
+                        Self::get_cage_technique(&self.derived_cages, cage, Technique::Combination),
+                        &[cage],
+                        init_board,
+                        &self.board,
+                    );
+                }
+                Ok(true)
+            } else {
+                Ok(progress)
+            }
         })
     }
 
-    fn reduce_by_partition(&mut self) -> Result<bool, ()> {
+    fn reduce_by_partition(&mut self) -> Result<bool, SolverError> {
+        if !self.config.allows(Technique::Partition) {
+            return Ok(false);
+        }
         let mut progress = false;
         loop {
+            let recording = self.is_recording();
             let substitutions = self.cages.iter().try_fold(vec![], |mut accum, cage| {
-                if let Some(res) = cage.check_for_partitions(&mut self.board)? {
-                    accum.push((cage.clone(), res))
+                let init_board = recording.then(|| self.board.clone());
+                if let Some((new_cage, remaining_cage)) = cage
+                    .check_for_partitions(&mut self.board)
+                    .map_err(|error| error.in_cage(cage))?
+                {
+                    if let Some(init_board) = &init_board {
+                        Step::record(
+                            &mut self.trace,
+                            self.config.observer.as_deref(),
+                            Technique::Partition,
+                            &[cage, &new_cage, &remaining_cage],
+                            init_board,
+                            &self.board,
+                        );
+                    }
+                    if let Some(observer) = self.config.observer.as_deref() {
+                        observer.on_partition(cage, &new_cage, &remaining_cage);
+                    }
+                    accum.push((cage.clone(), (new_cage, remaining_cage)))
                 }
                 Ok(accum)
             })?;
-            if substitutions.len() > 0 {
+            if !substitutions.is_empty() {
                 progress = true;
+                self.cage_index = None;
                 substitutions.into_iter().for_each(
                     |(original_cage, (new_cage, remaining_cage))| {
                         self.cages.remove(&original_cage);
+                        if self.derived_cages.remove(&original_cage) {
+                            self.derived_cages.insert(new_cage.clone());
+                            self.derived_cages.insert(remaining_cage.clone());
+                        }
                         self.cages.insert(new_cage);
                         self.cages.insert(remaining_cage);
                     },
@@ -185,13 +561,191 @@ impl Puzzle {
         Ok(progress)
     }
 
-    fn solve_until_stuck(&mut self) -> Result<bool, ()> {
-        self.cages.iter().try_for_each(|cage| {
-            cage.restrict_by_uniform_combination(&mut self.board)?;
-            Ok(())
-        })?;
+    /// If a value required by one cage is confined to cells which all belong to another cage with
+    /// uniqueness, then the value cannot appear anywhere else in the other cage
+    fn reduce_by_locked_candidates(&mut self) -> Result<bool, SolverError> {
+        if !self.config.allows(Technique::LockedCandidates) {
+            return Ok(false);
+        }
+        let recording = self.is_recording();
+        let mut progress = false;
+        for cage in self.cages.iter().filter(|cage| cage.uniqueness) {
+            for value in PossibleValues::new(cage.get_required_values(&self.board)?) {
+                let locked_cells = cage
+                    .cells
+                    .iter()
+                    .filter(|cell_index| self.board[**cell_index].allows(value))
+                    .cloned()
+                    .collect::<Vec<usize>>();
+                if locked_cells.is_empty() {
+                    return Err(SolverError::contradiction().in_cage(cage));
+                }
+                for other_cage in self.cages.iter().filter(|other_cage| {
+                    other_cage.uniqueness
+                        && *other_cage != cage
+                        && locked_cells
+                            .iter()
+                            .all(|cell_index| other_cage.cells.binary_search(cell_index).is_ok())
+                }) {
+                    let init_board = recording.then(|| self.board.clone());
+                    let mut cage_progress = false;
+                    for cell_index in other_cage.cells.iter() {
+                        if locked_cells.binary_search(cell_index).is_err()
+                            && self.board[*cell_index].allows(value)
+                        {
+                            self.board[*cell_index]
+                                .restrict_to(!(1 << value))
+                                .map_err(|error| error.at_cell(*cell_index))?;
+                            cage_progress = true;
+                        }
+                    }
+                    if let (true, Some(init_board)) = (cage_progress, &init_board) {
+                        Step::record(
+                            &mut self.trace,
+                            self.config.observer.as_deref(),
+                            Technique::LockedCandidates,
+                            &[cage, other_cage],
+                            init_board,
+                            &self.board,
+                        );
+                    }
+                    progress |= cage_progress;
+                }
+            }
+        }
+        Ok(progress)
+    }
+
+    /// X-Wing, Swordfish, and Jellyfish over the rows and columns
+    fn reduce_by_fish(&mut self) -> Result<bool, SolverError> {
+        [Technique::XWing, Technique::Swordfish, Technique::Jellyfish]
+            .into_iter()
+            .zip(2..=4)
+            .try_fold(false, |progress, (technique, size)| {
+                Ok(progress
+                    || self.apply_technique(technique, |board, cages| {
+                        restrict_by_fish(board, size, cages)
+                    })?)
+            })
+    }
+
+    /// Wings, colouring, and chains over the links between candidates, from simplest to hardest
+    fn reduce_by_chains(&mut self) -> Result<bool, SolverError> {
+        if ![
+            Technique::XyWing,
+            Technique::XyzWing,
+            Technique::SimpleColouring,
+            Technique::XChain,
+            Technique::AlternatingInferenceChain,
+        ]
+        .into_iter()
+        .any(|technique| self.config.allows(technique))
+        {
+            return Ok(false);
+        }
+        let graph = LinkGraph::new(&self.board, &self.cages, &self.config.cancellation)?;
+        Ok(self.apply_technique(Technique::XyWing, |board, cages| {
+            restrict_by_xy_wing(board, &graph, cages)
+        })? || self.apply_technique(Technique::XyzWing, |board, cages| {
+            restrict_by_xyz_wing(board, &graph, cages)
+        })? || self.apply_technique(Technique::SimpleColouring, |board, cages| {
+            restrict_by_simple_colouring(board, &graph, cages)
+        })? || self.apply_technique(Technique::XChain, |board, cages| {
+            restrict_by_x_chain(board, &graph, cages)
+        })? || self.apply_technique(Technique::AlternatingInferenceChain, |board, cages| {
+            restrict_by_aic(board, &graph, cages)
+        })?)
+    }
+
+    /// Almost locked sets from the houses and the killer cages
+    fn reduce_by_almost_locked_sets(&mut self) -> Result<bool, SolverError> {
+        if ![
+            Technique::AlsXz,
+            Technique::AlsXyWing,
+            Technique::DeathBlossom,
+        ]
+        .into_iter()
+        .any(|technique| self.config.allows(technique))
+        {
+            return Ok(false);
+        }
+        let cancellation = self.config.cancellation.clone();
+        let graph = LinkGraph::new(&self.board, &self.cages, &cancellation)?;
+        let sets = get_almost_locked_sets(&self.board, &self.cages, &graph, &cancellation)?;
+        Ok(self.apply_technique(Technique::AlsXz, |board, cages| {
+            restrict_by_als_xz(board, &sets, cages)
+        })? || self.apply_technique(Technique::AlsXyWing, |board, cages| {
+            restrict_by_als_xy_wing(board, &sets, cages, &cancellation)
+        })? || self.apply_technique(Technique::DeathBlossom, |board, cages| {
+            restrict_by_death_blossom(board, &sets, cages, &cancellation)
+        })?)
+    }
+
+    /// Add cages whose sums are implied by linear combinations of the existing cages; returns true
+    /// only if the new cages led to a restriction of the board
+    fn reduce_by_linear_system(&mut self) -> Result<bool, SolverError> {
+        if !self.config.allows(Technique::DerivedCage) {
+            return Ok(false);
+        }
+        let mut progress = false;
+        for cage in get_implied_cages(
+            &self.cages,
+            &self.board,
+            self.config.max_derived_cage_size,
+            &self.config.cancellation,
+        )? {
+            if !self
+                .cages
+                .iter()
+                .any(|other_cage| other_cage.cells == cage.cells)
+            {
+                let init_board = self.is_recording().then(|| self.board.clone());
+                let cage_progress = cage
+                    .restrict_by_uniform_combination(&mut self.board)
+                    .and_then(|uniform_progress| {
+                        Ok(cage.restrict_by_combination(&mut self.board)? | uniform_progress)
+                    })
+                    .map_err(|error| error.in_cage(&cage))?;
+                if let (true, Some(init_board)) = (cage_progress, &init_board) {
+                    Step::record(
+                        &mut self.trace,
+                        self.config.observer.as_deref(),
+                        Technique::DerivedCage,
+                        &[&cage],
+                        init_board,
+                        &self.board,
+                    );
+                }
+                progress |= cage_progress;
+                self.derived_cages.insert(cage.clone());
+                self.cages.insert(cage);
+                self.cage_index = None;
+            }
+        }
+        Ok(progress)
+    }
+
+    fn solve_until_stuck(&mut self) -> Result<bool, SolverError> {
+        self.derive_cages()?;
+        self.reduce_by_uniform_combination()?;
         self.reduce_by_partition()?;
-        while self.reduce_by_combination()? {
+        loop {
+            self.config.cancellation.check()?;
+            while self.reduce_by_bounds()?
+                | self.reduce_by_combination()?
+                | self.reduce_by_locked_candidates()?
+            {
+                self.config.cancellation.check()?;
+                self.reduce_by_partition()?;
+            }
+            if self.board.iter().all(|cell| cell.get_solution().is_some())
+                || !(self.reduce_by_fish()?
+                    || self.reduce_by_linear_system()?
+                    || self.reduce_by_chains()?
+                    || self.reduce_by_almost_locked_sets()?)
+            {
+                break;
+            }
             self.reduce_by_partition()?;
         }
 
@@ -199,53 +753,406 @@ impl Puzzle {
         Ok(self.board.iter().all(|cell| cell.get_solution().is_some()))
     }
 
-    fn solve_until_stuck_then_guess_and_fork(&mut self, depth: usize) -> Result<Vec<Puzzle>, ()> {
-        const RECURSION_LIMIT: usize = 10;
-        if self.solve_until_stuck()? {
-            Ok(vec![self.clone()])
-        } else if depth > RECURSION_LIMIT {
-            Err(())
-        } else {
-            /* Figure out how many cells each cell influences */
-            let mut cage_count = vec![BTreeSet::new(); 81];
-            for cage in self.cages.iter() {
-                if cage.uniqueness {
-                    for cell_index_a in cage.cells.iter() {
-                        for cell_index_b in cage.cells.iter() {
-                            cage_count[*cell_index_a].insert(*cell_index_b);
-                        }
+    /// Restrict a cell, then apply the techniques which only look at a single cage to the cages
+    /// containing each cell which changes, until nothing more changes; the board is left as it was
+    /// if there is a contradiction
+    fn restrict_and_propagate(
+        &mut self,
+        cell_index: usize,
+        values: u64,
+    ) -> Result<Vec<Step>, SolverError> {
+        self.derive_cages()?;
+        let init_board = self.board.clone();
+        let mut deductions = Some(vec![]);
+        let result = self.propagate(cell_index, values, &mut deductions);
+        if result.is_err() {
+            self.board = init_board;
+        }
+        result?;
+        let deductions = deductions.unwrap_or_default();
+        if let Some(trace) = &mut self.trace {
+            trace.extend(deductions.iter().cloned());
+        }
+        Ok(deductions)
+    }
+
+    /// Get the index of the cages containing each cell, building it if the cages have changed
+    fn get_cage_index(&mut self) -> Arc<CageIndex> {
+        let num_cells = self.board.len();
+        let cages = &self.cages;
+        self.cage_index
+            .get_or_insert_with(|| {
+                let cages = cages.iter().cloned().collect::<Vec<Cage>>();
+                let mut cell_cages = vec![vec![]; num_cells];
+                for (cage_index, cage) in cages.iter().enumerate() {
+                    for cell_index in cage.cells.iter() {
+                        cell_cages[*cell_index].push(cage_index);
+                    }
+                }
+                Arc::new(CageIndex { cages, cell_cages })
+            })
+            .clone()
+    }
+
+    fn propagate(
+        &mut self,
+        cell_index: usize,
+        values: u64,
+        deductions: &mut Option<Vec<Step>>,
+    ) -> Result<(), SolverError> {
+        let index = self.get_cage_index();
+        let CageIndex { cages, cell_cages } = index.as_ref();
+        self.board[cell_index]
+            .restrict_to(values)
+            .map_err(|error| error.at_cell(cell_index).on_board(&self.board))?;
+
+        let mut pending = cell_cages[cell_index]
+            .iter()
+            .cloned()
+            .collect::<BTreeSet<usize>>();
+        let restrictions: [(Technique, CageRestriction); 3] = [
+            (
+                Technique::UniformCombination,
+                Cage::restrict_by_uniform_combination,
+            ),
+            (Technique::Bounds, Cage::restrict_by_bounds),
+            (Technique::Combination, Cage::restrict_by_combination),
+        ];
+        while let Some(cage_index) = pending.pop_first() {
+            let cage = &cages[cage_index];
+            for (technique, restrict) in restrictions {
+                if !self.config.allows(technique) {
+                    continue;
+                }
+                /* Only the cells of the cage can change, so the rest of the board is not copied
+                 * unless there is a deduction to record */
+                let init_cells = cage
+                    .cells
+                    .iter()
+                    .map(|cell_index| self.board[*cell_index])
+                    .collect::<Vec<Cell>>();
+                restrict(cage, &mut self.board)
+                    .map_err(|error| error.in_cage(cage).on_board(&self.board))?;
+                let changed_cells = cage
+                    .cells
+                    .iter()
+                    .zip(init_cells.iter())
+                    .filter(|(cell_index, init_cell)| self.board[**cell_index] != **init_cell)
+                    .collect::<Vec<(&usize, &Cell)>>();
+                if !changed_cells.is_empty() {
+                    let mut init_board = self.board.clone();
+                    for (changed_cell_index, init_cell) in changed_cells {
+                        init_board[*changed_cell_index] = *init_cell;
+                        pending.extend(cell_cages[*changed_cell_index].iter());
                     }
+                    Step::record(
+                        deductions,
+                        self.config.observer.as_deref(),
+                        Self::get_cage_technique(&self.derived_cages, cage, technique),
+                        &[cage],
+                        &init_board,
+                        &self.board,
+                    );
                 }
             }
-            let guess_index = cage_count
-                .into_iter()
-                .enumerate()
-                .filter(|(i, _)| self.board[*i].get_solution().is_none())
-                .max_by_key(|(i, cells)| cells.len() / self.board[*i].num_possible_solutions())
-                .map(|(i, _)| i)
-                .unwrap();
-            let res = self.board[guess_index]
-                .possible_values()
-                .map(|guess_value| {
-                    let guess_index = guess_index;
-                    let mut puzzle_copy = self.clone();
-                    thread::spawn(move || {
-                        puzzle_copy.board[guess_index]
-                            .restrict_to(1 << guess_value)
-                            .unwrap();
-                        puzzle_copy.solve_until_stuck_then_guess_and_fork(depth + 1)
-                    })
-                })
-                .filter_map(|handle| handle.join().unwrap().ok())
-                .flatten()
-                .collect();
-            Ok(res)
         }
+        Ok(())
     }
 
-    pub fn solve(&self) -> Result<Vec<Puzzle>, ()> {
+    /// Place a value in a cell and make the deductions which follow from it straight away
+    #[allow(unused)]
+    pub fn place(&mut self, cell_index: usize, value: usize) -> Result<Vec<Step>, SolverError> {
+        self.restrict_and_propagate(cell_index, 1u64.checked_shl(value as u32).unwrap_or(0))
+    }
+
+    /// Remove a candidate from a cell and make the deductions which follow from it straight away
+    #[allow(unused)]
+    pub fn eliminate(&mut self, cell_index: usize, value: usize) -> Result<Vec<Step>, SolverError> {
+        self.restrict_and_propagate(cell_index, !1u64.checked_shl(value as u32).unwrap_or(0))
+    }
+
+    /// Find the simplest deduction which can be made from the current state of the board, without
+    /// solving any further
+    #[allow(unused)]
+    pub fn next_hint(&self) -> Result<Option<Step>, SolverError> {
         let mut working_copy = self.clone();
-        working_copy.solve_until_stuck_then_guess_and_fork(0)
+        working_copy.trace = Some(vec![]);
+        working_copy.derive_cages()?;
+        working_copy.reduce_by_uniform_combination()?;
+        working_copy.reduce_by_partition()?;
+        let find_step = |puzzle: &Self| {
+            puzzle
+                .trace
+                .iter()
+                .flatten()
+                .find(|step| !step.eliminations.is_empty())
+                .cloned()
+        };
+        if let Some(step) = find_step(&working_copy) {
+            return Ok(Some(step));
+        }
+        let reductions: [Reduction; 7] = [
+            Self::reduce_by_bounds,
+            Self::reduce_by_combination,
+            Self::reduce_by_locked_candidates,
+            Self::reduce_by_fish,
+            Self::reduce_by_linear_system,
+            Self::reduce_by_chains,
+            Self::reduce_by_almost_locked_sets,
+        ];
+        for reduce in reductions {
+            reduce(&mut working_copy)?;
+            if let Some(step) = find_step(&working_copy) {
+                return Ok(Some(step));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Choose the unsolved cell which influences the most other cells per possible value
+    fn get_guess_index(&self) -> usize {
+        /* Figure out how many cells each cell influences */
+        let mut cage_count = vec![BTreeSet::new(); self.board.len()];
+        for cage in self.cages.iter() {
+            if cage.uniqueness {
+                for cell_index_a in cage.cells.iter() {
+                    for cell_index_b in cage.cells.iter() {
+                        cage_count[*cell_index_a].insert(*cell_index_b);
+                    }
+                }
+            }
+        }
+        cage_count
+            .into_iter()
+            .enumerate()
+            /* Skip solved cells, and empty cells which the solver reports as contradictions */
+            .filter(|(i, _)| self.board[*i].num_possible_solutions() > 1)
+            .max_by_key(|(i, cells)| cells.len() / self.board[*i].num_possible_solutions())
+            .map(|(i, _)| i)
+            .unwrap()
+    }
+
+    /// The cell and value of each guess in a trace, from the first
+    fn get_guess_path(trace: &[Step]) -> Vec<(usize, usize)> {
+        trace
+            .iter()
+            .filter(|step| step.technique == Technique::Guess)
+            .flat_map(|step| step.placements.iter())
+            .map(|placement| (placement.cell_index, placement.value))
+            .collect()
+    }
+
+    /// Add a backtrack step before each guess for each value tried before it in the same cell
+    /// which was refuted, that is, which led to no solution and was not abandoned at the limit of
+    /// guesses. This is only known once the search is over, since the branches run in parallel.
+    fn record_backtracks(solutions: &mut [Puzzle], abandoned_paths: Vec<Vec<(usize, usize)>>) {
+        let mut unrefuted = BTreeSet::new();
+        let solution_paths = solutions
+            .iter()
+            .map(|solution| Self::get_guess_path(solution.get_trace().unwrap_or_default()));
+        for path in abandoned_paths.into_iter().chain(solution_paths) {
+            for len in 1..=path.len() {
+                unrefuted.insert(path[..len].to_vec());
+            }
+        }
+        for solution in solutions.iter_mut() {
+            let Some(trace) = solution.trace.take() else {
+                continue;
+            };
+            let mut output = vec![];
+            let mut path = vec![];
+            for mut step in trace {
+                if let (Technique::Guess, [guess], [elimination]) = (
+                    step.technique,
+                    &step.placements[..],
+                    &mut step.eliminations[..],
+                ) {
+                    /* The values are tried in order, so each smaller value was tried first */
+                    elimination.values.retain(|value| {
+                        let mut sibling_path = path.clone();
+                        sibling_path.push((guess.cell_index, *value));
+                        let refuted = *value < guess.value && !unrefuted.contains(&sibling_path);
+                        if refuted {
+                            output.push(Step::backtrack(&Placement {
+                                cell_index: guess.cell_index,
+                                value: *value,
+                            }));
+                        }
+                        !refuted
+                    });
+                    path.push((guess.cell_index, guess.value));
+                }
+                output.push(step);
+            }
+            solution.trace = Some(output);
+        }
+    }
+
+    /// Solve as far as possible, then guess the value of a cell and search each guess on the
+    /// worker pool; stops searching once limit solutions have been found. Branches which nest
+    /// guesses too deeply are abandoned, and only reported if no solution is found.
+    fn solve_until_stuck_then_guess_and_fork(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<Puzzle>, SolverError> {
+        let max_guess_depth = self.config.max_guess_depth;
+        /* The furthest the techniques got before guessing, in case the search is cancelled */
+        let unguessed_board = OnceLock::new();
+        let abandoned = AtomicBool::new(false);
+        let abandoned_paths = Mutex::new(vec![]);
+        let observer = self.config.observer.as_deref();
+        let mut solutions = search(
+            (0, None, self.clone()),
+            self.config.num_threads,
+            limit,
+            |(depth, guess, mut puzzle): (usize, Option<Placement>, Puzzle)| {
+                if let (Some(observer), Some(guess)) = (observer, &guess) {
+                    observer.on_guess(guess.cell_index, guess.value, depth);
+                }
+                /* Without the techniques which check the cells, an empty cell is only noticed here */
+                let result = puzzle.solve_until_stuck().and_then(|solved| {
+                    match puzzle.board.iter().position(|cell| cell.get_bits() == 0) {
+                        Some(cell_index) => Err(SolverError::contradiction().at_cell(cell_index)),
+                        None => Ok(solved),
+                    }
+                });
+                match result {
+                    Ok(true) => Ok(Expansion::Leaf(Some(puzzle))),
+                    /* The board after a guess is not a partial result */
+                    Err(error @ SolverError::Cancelled(_)) if depth > 0 => Err(error),
+                    /* A wrong guess leads to a contradiction */
+                    Err(_) if depth > 0 => {
+                        if let (Some(observer), Some(guess)) = (observer, &guess) {
+                            observer.on_step(&Step::backtrack(guess));
+                            observer.on_backtrack(guess.cell_index, guess.value, depth);
+                        }
+                        Ok(Expansion::Leaf(None))
+                    }
+                    Err(error) => Err(error.on_board(&puzzle.board)),
+                    /* An incomplete search cannot be ignored */
+                    Ok(false) if !puzzle.config.allow_guessing => {
+                        Err(SolverError::Stuck(Location::default()).on_board(&puzzle.board))
+                    }
+                    Ok(false) if depth > max_guess_depth => {
+                        if let Some(trace) = puzzle.get_trace() {
+                            abandoned_paths
+                                .lock()
+                                .unwrap()
+                                .push(Self::get_guess_path(trace));
+                        }
+                        abandoned.store(true, Ordering::Relaxed);
+                        Ok(Expansion::Leaf(None))
+                    }
+                    Ok(false) => {
+                        if depth == 0 {
+                            let _ = unguessed_board.set(puzzle.board.clone());
+                        }
+                        let guess_index = puzzle.get_guess_index();
+                        let mut children = vec![];
+                        for guess_value in puzzle.board[guess_index].possible_values() {
+                            let mut puzzle_copy = puzzle.clone();
+                            puzzle_copy.apply_technique(Technique::Guess, |board, _| {
+                                board[guess_index]
+                                    .restrict_to(1 << guess_value)
+                                    .map_err(|error| error.at_cell(guess_index))?;
+                                Ok(true)
+                            })?;
+                            let guess = Placement {
+                                cell_index: guess_index,
+                                value: guess_value,
+                            };
+                            children.push((depth + 1, Some(guess), puzzle_copy));
+                        }
+                        Ok(Expansion::Branch(children))
+                    }
+                }
+            },
+        )
+        .map_err(|error| match (error, unguessed_board.get()) {
+            (error @ SolverError::Cancelled(_), Some(board)) => error.on_board(board),
+            (error, _) => error,
+        })?;
+        if solutions.is_empty() && abandoned.into_inner() {
+            let error = SolverError::RecursionLimit {
+                depth: max_guess_depth,
+                location: Location::default(),
+            };
+            return Err(match unguessed_board.get() {
+                Some(board) => error.on_board(board),
+                None => error,
+            });
+        }
+        Self::record_backtracks(&mut solutions, abandoned_paths.into_inner().unwrap());
+        Ok(solutions)
+    }
+
+    pub fn solve(&self, config: &SolverConfig) -> Result<Vec<Puzzle>, SolverError> {
+        self.solve_with_backend(Backend::Logic, config)
+    }
+
+    pub fn solve_with_backend(
+        &self,
+        backend: Backend,
+        config: &SolverConfig,
+    ) -> Result<Vec<Puzzle>, SolverError> {
+        self.solve_with_limit(backend, config, usize::MAX)
+    }
+
+    /// Find at most limit solutions
+    pub fn solve_with_limit(
+        &self,
+        backend: Backend,
+        config: &SolverConfig,
+        limit: usize,
+    ) -> Result<Vec<Puzzle>, SolverError> {
+        match backend {
+            Backend::Logic => {
+                let mut working_copy = self.clone();
+                working_copy.config = config.clone();
+                working_copy.solve_until_stuck_then_guess_and_fork(limit)
+            }
+            Backend::ExactCover => Ok(solve_board_by_exact_cover(
+                &self.board,
+                &self.given_cages,
+                limit,
+                &config.cancellation,
+            )?
+            .into_iter()
+            .map(|board| Puzzle {
+                board,
+                ..self.clone()
+            })
+            .collect()),
+        }
+    }
+
+    /// Count the solutions by brute force, stopping as soon as limit solutions have been found
+    pub fn count_solutions(
+        &self,
+        limit: usize,
+        config: &SolverConfig,
+    ) -> Result<usize, SolverError> {
+        Ok(self
+            .solve_with_limit(Backend::ExactCover, config, limit)?
+            .len())
+    }
+
+    pub fn has_unique_solution(&self, config: &SolverConfig) -> Result<bool, SolverError> {
+        Ok(self.count_solutions(2, config)? == 1)
+    }
+
+    /// Encode the puzzle in DIMACS CNF format for an external SAT solver
+    #[allow(unused)]
+    pub fn to_dimacs(&self) -> Result<String, SolverError> {
+        Ok(Cnf::from_board(&self.board, &self.cages)?.to_dimacs())
+    }
+
+    /// Get a copy of the puzzle solved according to the model found by an external SAT solver
+    #[allow(unused)]
+    pub fn apply_sat_model(&self, model: &str) -> Result<Puzzle, SolverError> {
+        let mut output = self.clone();
+        apply_model(&mut output.board, model)?;
+        Ok(output)
     }
 }
 
@@ -262,3 +1169,435 @@ impl Display for Puzzle {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ks::cancel::CancellationToken;
+    use std::time::Duration;
+
+    #[test]
+    fn house_unions() {
+        /* Three boxes in a band or stack coincide with three rows or columns */
+        let unions = Puzzle::get_house_unions(&Shape::default());
+        assert_eq!(unions.len(), 42);
+        assert_eq!(unions.iter().filter(|cage| cage.sum == 90).count(), 28);
+        assert!(unions.iter().all(|cage| cage.cells.len() * 5 == cage.sum));
+    }
+
+    #[test]
+    fn derive_cages_from_uncovered_house() {
+        /* Cells 2 to 8 of the first row are in no cage */
+        let mut puzzle = Puzzle::new();
+        puzzle.init_cages(vec![(3, vec![0, 1])], false);
+        puzzle.derivation_pending = true;
+        assert!(matches!(
+            puzzle.derive_cages(),
+            Err(SolverError::Contradiction(_))
+        ));
+    }
+
+    #[test]
+    fn derived_cages_match_solution() {
+        let serialized_puzzle =
+            SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap();
+        let cell_values = serialized_puzzle.cell_values.clone();
+        let mut puzzle = Puzzle::from_serializable(serialized_puzzle).unwrap();
+        puzzle.derive_cages().unwrap();
+        for cage in puzzle.cages.iter() {
+            let values = cage
+                .cells
+                .iter()
+                .map(|cell_index| cell_values[*cell_index])
+                .collect::<Vec<usize>>();
+            assert_eq!(values.iter().sum::<usize>(), cage.sum, "{}", cage);
+            if cage.uniqueness {
+                let value_set = values.iter().collect::<BTreeSet<&usize>>();
+                assert_eq!(value_set.len(), values.len(), "{}", cage);
+            }
+        }
+    }
+
+    #[test]
+    fn trace_eliminates_only_wrong_values() {
+        let serialized_puzzle =
+            SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap();
+        let cell_values = serialized_puzzle.cell_values.clone();
+        let mut puzzle = Puzzle::from_serializable(serialized_puzzle).unwrap();
+        puzzle.enable_trace();
+        let solutions = puzzle.solve(&SolverConfig::default()).unwrap();
+        let trace = solutions[0].get_trace().unwrap();
+        assert!(!trace.is_empty());
+        for step in trace.iter() {
+            for elimination in step.eliminations.iter() {
+                assert!(
+                    !elimination
+                        .values
+                        .contains(&cell_values[elimination.cell_index]),
+                    "{}",
+                    step
+                );
+            }
+        }
+        assert!(serde_json::to_string(trace).is_ok());
+    }
+
+    #[test]
+    fn next_hint_is_simplest() {
+        /* A cage of two cells summing to 3 must be {1, 2} */
+        let mut puzzle = Puzzle::new();
+        puzzle.cages.insert(Cage::from_parts(vec![0, 1], 3, true));
+        let step = puzzle.next_hint().unwrap().unwrap();
+        assert_eq!(step.technique, Technique::UniformCombination);
+        assert_eq!(step.cages[0].cell_indices, vec![0, 1]);
+        assert_eq!(step.eliminations.len(), 2);
+        assert_eq!(puzzle.board[0].num_possible_solutions(), 9);
+        assert!(Puzzle::new().next_hint().unwrap().is_none());
+    }
+
+    #[test]
+    fn count_solutions() {
+        let puzzle = Puzzle::new();
+        let config = SolverConfig::default();
+        assert_eq!(puzzle.count_solutions(3, &config).unwrap(), 3);
+        assert!(!puzzle.has_unique_solution(&config).unwrap());
+        assert_eq!(
+            puzzle
+                .solve_with_limit(Backend::ExactCover, &config, 0)
+                .unwrap()
+                .len(),
+            0
+        );
+
+        let serialized_puzzle =
+            SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap();
+        let num_cages = serialized_puzzle.cages.len();
+        let mut puzzle = Puzzle::from_serializable(serialized_puzzle).unwrap();
+        puzzle.config = config.clone();
+        puzzle.derive_cages().unwrap();
+        assert_eq!(puzzle.given_cages.len(), 27 + num_cages);
+        assert!(puzzle.cages.len() > puzzle.given_cages.len());
+        assert!(puzzle.has_unique_solution(&config).unwrap());
+        assert_eq!(
+            puzzle
+                .solve_with_limit(Backend::Logic, &config, 1)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn contradiction_location() {
+        let mut puzzle = Puzzle::new();
+        puzzle.cages.insert(Cage::from_parts(vec![0, 1], 3, true));
+        puzzle.board[0].restrict_to(1 << 5).unwrap();
+        match puzzle.solve(&SolverConfig::default()) {
+            Err(SolverError::Contradiction(location)) => {
+                assert_eq!(location.cell_index, Some(0));
+                assert_eq!(location.cage.unwrap().cells, vec![0, 1]);
+                assert!(location.board.is_some());
+            }
+            _ => panic!("Expected a contradiction"),
+        }
+    }
+
+    #[test]
+    fn locked_candidates() {
+        /* Confine 1 to the top row of the first box */
+        let mut puzzle = Puzzle::new();
+        for cell_index in [9, 10, 11, 18, 19, 20] {
+            puzzle.board[cell_index].restrict_to(!(1 << 1)).unwrap();
+        }
+        assert!(puzzle.reduce_by_locked_candidates().unwrap());
+        assert!((0..3).all(|cell_index| puzzle.board[cell_index].allows(1)));
+        assert!((3..9).all(|cell_index| !puzzle.board[cell_index].allows(1)));
+        assert!((12..18).all(|cell_index| puzzle.board[cell_index].allows(1)));
+    }
+
+    #[test]
+    fn solver_config() {
+        let puzzle = Puzzle::new();
+        assert!(matches!(
+            puzzle.solve(&SolverConfig::logic_only()),
+            Err(SolverError::Stuck(_))
+        ));
+        let config = SolverConfig {
+            max_guess_depth: 0,
+            ..SolverConfig::default()
+        };
+        assert!(matches!(
+            puzzle.solve(&config),
+            Err(SolverError::RecursionLimit { depth: 0, .. })
+        ));
+
+        /* The test puzzle needs an innie beyond the basic techniques */
+        let serialized_puzzle =
+            SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap();
+        let mut puzzle = Puzzle::from_serializable(serialized_puzzle).unwrap();
+        puzzle.enable_trace();
+        assert!(matches!(
+            puzzle.solve(&SolverConfig::basic()),
+            Err(SolverError::Stuck(_))
+        ));
+        let mut config = SolverConfig::basic();
+        config.techniques.insert(Technique::DerivedCage);
+        let solutions = puzzle.solve(&config).unwrap();
+        assert_eq!(solutions.len(), 1);
+        let trace = solutions[0].get_trace().unwrap();
+        assert!(trace
+            .iter()
+            .any(|step| step.technique == Technique::DerivedCage));
+        assert!(trace
+            .iter()
+            .all(|step| step.technique == Technique::DerivedCage
+                || step.technique.get_difficulty() == 1));
+    }
+
+    /// The solution of the test puzzle with only some of its values given, and no cages other than
+    /// the houses
+    fn partly_given_puzzle(num_givens: usize) -> (Puzzle, Vec<usize>) {
+        let serialized_puzzle =
+            SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap();
+        let cell_values = serialized_puzzle.cell_values.clone();
+        let mut puzzle = Puzzle::with_shape(Shape::default());
+        for cell_index in [
+            0, 3, 8, 12, 14, 15, 16, 19, 26, 29, 30, 32, 33, 35, 47, 48, 50, 54, 55, 56, 59, 60,
+            61, 63, 64, 66, 68, 69, 70, 71, 72, 74, 75, 76, 77, 78, 80,
+        ]
+        .into_iter()
+        .take(num_givens)
+        {
+            puzzle.board[cell_index]
+                .restrict_to(1 << cell_values[cell_index])
+                .unwrap();
+        }
+        (puzzle, cell_values)
+    }
+
+    #[test]
+    fn trace_records_backtracks() {
+        /* With only the combinations of the houses, the first guess is wrong */
+        let (mut puzzle, cell_values) = partly_given_puzzle(37);
+        puzzle.enable_trace();
+        let config = SolverConfig {
+            techniques: BTreeSet::from([Technique::Combination]),
+            ..SolverConfig::default()
+        };
+        let solutions = puzzle.solve_with_limit(Backend::Logic, &config, 1).unwrap();
+        let trace = solutions[0].get_trace().unwrap();
+        let position = trace
+            .iter()
+            .position(|step| step.technique == Technique::Backtrack)
+            .unwrap();
+        let elimination = &trace[position].eliminations[0];
+        assert_ne!(
+            elimination.values,
+            vec![cell_values[elimination.cell_index]]
+        );
+        assert_eq!(trace[position + 1].technique, Technique::Guess);
+
+        /* A value is only backtracked if it led to no solution */
+        let mut puzzle = Puzzle::with_shape(Shape::new(2, 2).unwrap());
+        puzzle.enable_trace();
+        let solutions = puzzle.solve_with_limit(Backend::Logic, &config, 3).unwrap();
+        assert_eq!(solutions.len(), 3);
+        assert!(solutions.iter().all(|solution| solution
+            .get_trace()
+            .unwrap()
+            .iter()
+            .all(|step| step.technique != Technique::Backtrack)));
+    }
+
+    #[test]
+    fn abandoned_branches() {
+        /* Some branches nest guesses deeper than the limit, but another reaches a solution */
+        let (puzzle, _) = partly_given_puzzle(27);
+        let config = |max_guess_depth| SolverConfig {
+            techniques: BTreeSet::from([Technique::Combination]),
+            max_guess_depth,
+            ..SolverConfig::default()
+        };
+        assert_eq!(
+            puzzle
+                .solve_with_limit(Backend::Logic, &config(3), 1)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(matches!(
+            puzzle.solve_with_limit(Backend::Logic, &config(2), 1),
+            Err(SolverError::RecursionLimit { depth: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn cancellation() {
+        let serialized_puzzle =
+            SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap();
+        let puzzle = Puzzle::from_serializable(serialized_puzzle).unwrap();
+        let config = SolverConfig::default();
+        config.cancellation.cancel();
+        for backend in [Backend::Logic, Backend::ExactCover] {
+            assert!(matches!(
+                puzzle.solve_with_backend(backend, &config),
+                Err(SolverError::Cancelled(_))
+            ));
+        }
+
+        /* The partial result is the board before guessing */
+        let config = SolverConfig {
+            cancellation: CancellationToken::with_timeout(Duration::ZERO),
+            ..SolverConfig::default()
+        };
+        match Puzzle::new().solve(&config) {
+            Err(SolverError::Cancelled(location)) => {
+                assert_eq!(location.board.unwrap(), Puzzle::new().board)
+            }
+            _ => panic!("Expected cancellation"),
+        }
+    }
+
+    #[test]
+    fn givens() {
+        let serialized_puzzle =
+            SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap();
+        let cell_values = serialized_puzzle.cell_values.clone();
+        let mut givens = vec![0; 81];
+        givens[40] = cell_values[40];
+        let puzzle = Puzzle::from_serializable(SerializablePuzzle {
+            givens: Some(givens.clone()),
+            ..SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap()
+        })
+        .unwrap();
+        assert_eq!(puzzle.board[40].get_solution(), Some(cell_values[40]));
+        assert_eq!(puzzle.board[41], Cell::default());
+
+        /* A wrong given makes the puzzle unsolvable */
+        givens[40] = cell_values[40] % 9 + 1;
+        let puzzle = Puzzle::from_serializable(SerializablePuzzle {
+            givens: Some(givens.clone()),
+            ..SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap()
+        })
+        .unwrap();
+        assert!(puzzle.solve(&SolverConfig::default()).is_err());
+
+        /* A given which does not fit the grid is rejected when loading */
+        givens[40] = 10;
+        let result = Puzzle::from_serializable(SerializablePuzzle {
+            givens: Some(givens.clone()),
+            ..SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap()
+        });
+        assert!(
+            matches!(result, Err(SolverError::InvalidInput(location)) if location.cell_index == Some(40))
+        );
+        givens.pop();
+        let result = Puzzle::from_serializable(SerializablePuzzle {
+            givens: Some(givens),
+            ..SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap()
+        });
+        assert!(matches!(result, Err(SolverError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn guess_with_empty_cell() {
+        /* With no techniques to notice the empty cell, the search must not guess it */
+        let mut puzzle = Puzzle::new();
+        assert!(puzzle.board[0].restrict_to(0).is_err());
+        let config = SolverConfig {
+            techniques: BTreeSet::new(),
+            ..SolverConfig::default()
+        };
+        assert!(matches!(
+            puzzle.solve(&config),
+            Err(SolverError::Contradiction(location)) if location.cell_index == Some(0)
+        ));
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let serialized_puzzle =
+            SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap();
+        let cell_values = serialized_puzzle.cell_values.clone();
+        let mut puzzle = Puzzle::from_serializable(serialized_puzzle).unwrap();
+        puzzle.derive_cages().unwrap();
+        puzzle.reduce_by_uniform_combination().unwrap();
+        puzzle.reduce_by_partition().unwrap();
+
+        let json = puzzle.to_snapshot().to_str().unwrap();
+        let restored_puzzle =
+            Puzzle::from_snapshot(SerializableSnapshot::from_str(&json).unwrap()).unwrap();
+        assert_eq!(restored_puzzle.board, puzzle.board);
+        assert_eq!(restored_puzzle.cages, puzzle.cages);
+        let solutions = restored_puzzle.solve(&SolverConfig::default()).unwrap();
+        for (cell, value) in solutions[0].board.iter().zip(cell_values.iter()) {
+            assert_eq!(cell.get_solution(), Some(*value));
+        }
+        assert!(restored_puzzle
+            .has_unique_solution(&SolverConfig::default())
+            .unwrap());
+
+        /* Candidates and cages which do not fit the grid are rejected */
+        let mut snapshot = SerializableSnapshot::from_str(&json).unwrap();
+        snapshot.candidates[0].push(10);
+        assert!(matches!(
+            Puzzle::from_snapshot(snapshot),
+            Err(SolverError::InvalidInput(location)) if location.cell_index == Some(0)
+        ));
+        let mut snapshot = SerializableSnapshot::from_str(&json).unwrap();
+        snapshot.candidates.pop();
+        assert!(Puzzle::from_snapshot(snapshot).is_err());
+        let mut snapshot = SerializableSnapshot::from_str(&json).unwrap();
+        snapshot.cages[0].cell_indices.push(81);
+        assert!(Puzzle::from_snapshot(snapshot).is_err());
+        let mut snapshot = SerializableSnapshot::from_str(&json).unwrap();
+        snapshot.candidates[0].clear();
+        assert!(matches!(
+            Puzzle::from_snapshot(snapshot),
+            Err(SolverError::Contradiction(location)) if location.cell_index == Some(0)
+        ));
+    }
+
+    #[test]
+    fn place_and_eliminate() {
+        /* Placing a value removes it from the houses of the cell, but nowhere else */
+        let mut puzzle = Puzzle::new();
+        let deductions = puzzle.place(0, 5).unwrap();
+        assert!(!deductions.is_empty());
+        assert!((1..9).all(|cell_index| !puzzle.board[cell_index].allows(5)));
+        assert!(!puzzle.board[20].allows(5));
+        assert!(puzzle.board[80].allows(5));
+
+        /* A cage of two cells summing to 3 must be {1, 2} */
+        let mut puzzle = Puzzle::new();
+        puzzle.cages.insert(Cage::from_parts(vec![0, 1], 3, true));
+        let board = puzzle.board.clone();
+        assert!(matches!(
+            puzzle.place(0, 5),
+            Err(SolverError::Contradiction(_))
+        ));
+        assert_eq!(puzzle.board, board);
+        puzzle.eliminate(0, 1).unwrap();
+        assert_eq!(puzzle.board[0].get_solution(), Some(2));
+        assert_eq!(puzzle.board[1].get_solution(), Some(1));
+    }
+
+    #[test]
+    fn trace_after_contradiction() {
+        /* The row of cell 0 is restricted before the cage in its column fails, but only the
+         * deductions of successful restrictions are kept */
+        let mut puzzle = Puzzle::new();
+        puzzle.enable_trace();
+        let mut trace = puzzle.place(80, 9).unwrap();
+        assert_eq!(puzzle.get_trace().unwrap().len(), trace.len());
+        puzzle.init_cages(vec![(3, vec![9, 18])], false);
+        assert!(matches!(
+            puzzle.place(0, 1),
+            Err(SolverError::Contradiction(_))
+        ));
+        assert_eq!(puzzle.get_trace().unwrap().len(), trace.len());
+        trace.extend(puzzle.eliminate(9, 2).unwrap());
+        assert_eq!(puzzle.get_trace().unwrap().len(), trace.len());
+        assert_eq!(puzzle.board[18].get_solution(), Some(2));
+    }
+}
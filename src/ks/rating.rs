@@ -0,0 +1,149 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::ks::{
+    config::SolverConfig,
+    error::SolverError,
+    io::SerializablePuzzle,
+    puzzle::Puzzle,
+    step::{Step, Technique},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Tier {
+    Easy,
+    Medium,
+    Hard,
+    Expert,
+    Extreme,
+}
+
+impl Tier {
+    fn from_difficulty(difficulty: usize) -> Self {
+        match difficulty {
+            0..=1 => Tier::Easy,
+            2..=3 => Tier::Medium,
+            4..=6 => Tier::Hard,
+            7..=9 => Tier::Expert,
+            _ => Tier::Extreme,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Rating {
+    /// Hundreds for the hardest technique and each level of guessing beyond the first, and units
+    /// for how much the techniques beyond the basics were used
+    pub score: usize,
+    pub tier: Tier,
+    pub hardest_technique: Option<Technique>,
+    pub technique_counts: BTreeMap<Technique, usize>,
+    pub guess_depth: usize,
+}
+
+impl Rating {
+    fn from_trace(trace: &[Step]) -> Self {
+        let mut technique_counts = BTreeMap::<Technique, usize>::new();
+        for step in trace.iter() {
+            *technique_counts.entry(step.technique).or_insert(0) += 1;
+        }
+        /* Taking back a guess is no harder than making it */
+        let hardest_technique = technique_counts
+            .keys()
+            .filter(|technique| **technique != Technique::Backtrack)
+            .max_by_key(|technique| technique.get_difficulty())
+            .cloned();
+        let difficulty = hardest_technique.map_or(0, |technique| technique.get_difficulty());
+        let guess_depth = technique_counts
+            .get(&Technique::Guess)
+            .cloned()
+            .unwrap_or(0);
+        let usage = trace
+            .iter()
+            .map(|step| step.technique.get_difficulty())
+            .filter(|difficulty| *difficulty > 1)
+            .sum::<usize>()
+            .min(99);
+        Self {
+            score: 100 * (difficulty + guess_depth.saturating_sub(1)) + usage,
+            tier: Tier::from_difficulty(difficulty),
+            hardest_technique,
+            technique_counts,
+            guess_depth,
+        }
+    }
+}
+
+/// Solve the puzzle, preferring logical techniques to guessing, and rate how hard it was; fails if
+/// the puzzle does not have a unique solution or cannot be solved with the techniques allowed
+pub fn rate(puzzle: SerializablePuzzle, config: &SolverConfig) -> Result<Rating, SolverError> {
+    let mut puzzle = Puzzle::from_serializable(puzzle)?;
+    puzzle.enable_trace();
+    match &puzzle.solve(config)?[..] {
+        [solution] => Ok(Rating::from_trace(solution.get_trace().unwrap_or_default())),
+        solutions => Err(SolverError::NotUnique {
+            num_solutions: solutions.len(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ks::{board::Board, cage::Cage};
+
+    #[test]
+    fn rate_puzzle() {
+        /* The test puzzle needs innies, but nothing harder */
+        let rating = rate(
+            SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap(),
+            &SolverConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(rating.tier, Tier::Medium);
+        assert_eq!(rating.hardest_technique, Some(Technique::DerivedCage));
+        assert_eq!(rating.guess_depth, 0);
+        assert!((300..400).contains(&rating.score));
+
+        /* With one given, the other needs an alternating inference chain instead of a guess */
+        let harder_rating = rate(
+            SerializablePuzzle::from_str(include_str!("test/puzzle_1.json")).unwrap(),
+            &SolverConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(harder_rating.tier, Tier::Expert);
+        assert_eq!(
+            harder_rating.hardest_technique,
+            Some(Technique::AlternatingInferenceChain)
+        );
+        assert_eq!(harder_rating.guess_depth, 0);
+        assert!((700..800).contains(&harder_rating.score));
+        assert!(harder_rating.score > rating.score);
+    }
+
+    #[test]
+    fn rate_trace() {
+        let board = Board::default();
+        let mut solved_board = board.clone();
+        solved_board[0].restrict_to(1 << 1).unwrap();
+        let cage = Cage::from_parts(vec![0], 1, true);
+        let step = |technique| Step::new(technique, &[&cage], &board, &solved_board);
+
+        let rating = Rating::from_trace(&[step(Technique::Combination)]);
+        assert_eq!((rating.score, rating.tier), (100, Tier::Easy));
+
+        let rating = Rating::from_trace(&[
+            step(Technique::Guess),
+            step(Technique::XWing),
+            step(Technique::Guess),
+        ]);
+        assert_eq!((rating.score, rating.tier), (1124, Tier::Extreme));
+        assert_eq!(rating.technique_counts[&Technique::Guess], 2);
+
+        let rating = Rating::from_trace(&[step(Technique::Backtrack), step(Technique::Guess)]);
+        assert_eq!(rating.hardest_technique, Some(Technique::Guess));
+    }
+}
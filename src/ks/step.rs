@@ -0,0 +1,287 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::{collections::BTreeSet, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ks::{
+    cage::Cage, cell::Cell, combinations::PossibleValues, io::SerializableCage, observer::Observer,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Technique {
+    UniformCombination,
+    Bounds,
+    Combination,
+    LockedCandidates,
+    Partition,
+    XWing,
+    Swordfish,
+    Jellyfish,
+    DerivedCage,
+    XyWing,
+    XyzWing,
+    SimpleColouring,
+    XChain,
+    AlternatingInferenceChain,
+    AlsXz,
+    AlsXyWing,
+    DeathBlossom,
+    Guess,
+    /// A guess which led to a contradiction is taken back
+    Backtrack,
+}
+
+impl Technique {
+    pub const ALL: [Technique; 19] = [
+        Technique::UniformCombination,
+        Technique::Bounds,
+        Technique::Combination,
+        Technique::LockedCandidates,
+        Technique::Partition,
+        Technique::XWing,
+        Technique::Swordfish,
+        Technique::Jellyfish,
+        Technique::DerivedCage,
+        Technique::XyWing,
+        Technique::XyzWing,
+        Technique::SimpleColouring,
+        Technique::XChain,
+        Technique::AlternatingInferenceChain,
+        Technique::AlsXz,
+        Technique::AlsXyWing,
+        Technique::DeathBlossom,
+        Technique::Guess,
+        Technique::Backtrack,
+    ];
+
+    /// How hard the technique is for a person to find, from 1 to 10
+    pub fn get_difficulty(&self) -> usize {
+        match self {
+            Technique::UniformCombination
+            | Technique::Bounds
+            | Technique::Combination
+            | Technique::Partition => 1,
+            Technique::LockedCandidates => 2,
+            Technique::DerivedCage => 3,
+            Technique::XWing | Technique::XyWing => 4,
+            Technique::Swordfish | Technique::XyzWing | Technique::SimpleColouring => 5,
+            Technique::Jellyfish | Technique::XChain => 6,
+            Technique::AlternatingInferenceChain | Technique::AlsXz => 7,
+            Technique::AlsXyWing => 8,
+            Technique::DeathBlossom => 9,
+            Technique::Guess | Technique::Backtrack => 10,
+        }
+    }
+}
+
+/// Values removed from a single cell
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Elimination {
+    pub cell_index: usize,
+    pub values: Vec<usize>,
+}
+
+/// A value which is the only one remaining in a cell
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Placement {
+    pub cell_index: usize,
+    pub value: usize,
+}
+
+/// A single deduction made by the solver
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Step {
+    pub technique: Technique,
+    pub cages: Vec<SerializableCage>,
+    pub eliminations: Vec<Elimination>,
+    pub placements: Vec<Placement>,
+}
+
+/// How much of a step to reveal to the player
+#[allow(unused)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HintLevel {
+    Region,
+    Technique,
+    Deduction,
+}
+
+#[allow(unused)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Hint {
+    /// The cells to look at
+    Region(Vec<usize>),
+    /// The cells to look at and the technique to apply to them
+    Technique(Technique, Vec<usize>),
+    /// Everything that can be deduced
+    Deduction(Step),
+}
+
+impl Step {
+    pub fn new(technique: Technique, cages: &[&Cage], init_board: &[Cell], board: &[Cell]) -> Self {
+        Self {
+            technique,
+            cages: cages
+                .iter()
+                .map(|cage| SerializableCage {
+                    sum: cage.sum,
+                    cell_indices: cage.cells.clone(),
+                })
+                .collect(),
+            eliminations: init_board
+                .iter()
+                .zip(board.iter())
+                .enumerate()
+                .filter_map(|(cell_index, (init_cell, cell))| {
+                    let values = init_cell.get_bits() & !cell.get_bits();
+                    (values != 0).then(|| Elimination {
+                        cell_index,
+                        values: PossibleValues::new(values).collect(),
+                    })
+                })
+                .collect(),
+            placements: init_board
+                .iter()
+                .zip(board.iter())
+                .enumerate()
+                .filter_map(|(cell_index, (init_cell, cell))| {
+                    match (init_cell.get_solution(), cell.get_solution()) {
+                        (None, Some(value)) => Some(Placement { cell_index, value }),
+                        _ => None,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// The removal of a guessed value which led to a contradiction
+    pub fn backtrack(guess: &Placement) -> Self {
+        Self {
+            technique: Technique::Backtrack,
+            cages: vec![],
+            eliminations: vec![Elimination {
+                cell_index: guess.cell_index,
+                values: vec![guess.value],
+            }],
+            placements: vec![],
+        }
+    }
+
+    /// The cells of the cages involved, or the cells changed if no cages were involved
+    fn get_region(&self) -> Vec<usize> {
+        let cells = if self.cages.is_empty() {
+            self.eliminations
+                .iter()
+                .map(|elimination| elimination.cell_index)
+                .collect::<BTreeSet<usize>>()
+        } else {
+            self.cages
+                .iter()
+                .flat_map(|cage| cage.cell_indices.iter().cloned())
+                .collect::<BTreeSet<usize>>()
+        };
+        cells.into_iter().collect()
+    }
+
+    /// Reveal the step gradually, from a region of the board to the full deduction
+    #[allow(unused)]
+    pub fn get_hint(&self, level: HintLevel) -> Hint {
+        match level {
+            HintLevel::Region => Hint::Region(self.get_region()),
+            HintLevel::Technique => Hint::Technique(self.technique, self.get_region()),
+            HintLevel::Deduction => Hint::Deduction(self.clone()),
+        }
+    }
+
+    /// Append a step to the trace, if a trace is being kept, and pass it to the observer
+    pub fn record(
+        trace: &mut Option<Vec<Step>>,
+        observer: Option<&dyn Observer>,
+        technique: Technique,
+        cages: &[&Cage],
+        init_board: &[Cell],
+        board: &[Cell],
+    ) {
+        if trace.is_none() && observer.is_none() {
+            return;
+        }
+        let step = Self::new(technique, cages, init_board, board);
+        if let Some(observer) = observer {
+            observer.on_step(&step);
+        }
+        if let Some(trace) = trace {
+            trace.push(step);
+        }
+    }
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.technique)?;
+        for cage in self.cages.iter() {
+            write!(f, " {:?} = {}", cage.cell_indices, cage.sum)?;
+        }
+        for elimination in self.eliminations.iter() {
+            write!(f, "; {}: -{:?}", elimination.cell_index, elimination.values)?;
+        }
+        for placement in self.placements.iter() {
+            write!(f, "; {} = {}", placement.cell_index, placement.value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ks::board::Board;
+
+    #[test]
+    fn step_eliminations() {
+        let init_board = Board::default();
+        let mut board = init_board.clone();
+        board[4].restrict_to(0b0110).unwrap();
+        board[7].restrict_to(!(1 << 9)).unwrap();
+        let cage = Cage::from_parts(vec![4, 7], 3, true);
+        let step = Step::new(Technique::Combination, &[&cage], &init_board, &board);
+        assert_eq!(
+            step.eliminations,
+            vec![
+                Elimination {
+                    cell_index: 4,
+                    values: vec![3, 4, 5, 6, 7, 8, 9]
+                },
+                Elimination {
+                    cell_index: 7,
+                    values: vec![9]
+                },
+            ]
+        );
+        assert_eq!(step.placements, vec![]);
+        let json = serde_json::to_string(&step).unwrap();
+        assert!(json.starts_with(r#"{"technique":"Combination","cages":[{"sum":3,"#));
+        let step: Step = serde_json::from_str(&json).unwrap();
+        assert_eq!(step.eliminations.len(), 2);
+    }
+
+    #[test]
+    fn step_hints() {
+        let init_board = Board::default();
+        let mut board = init_board.clone();
+        board[30].restrict_to(1 << 7).unwrap();
+        let step = Step::new(Technique::XWing, &[], &init_board, &board);
+        assert_eq!(
+            step.placements,
+            vec![Placement {
+                cell_index: 30,
+                value: 7
+            }]
+        );
+        assert!(matches!(step.get_hint(HintLevel::Region), Hint::Region(cells) if cells == [30]));
+        assert!(matches!(
+            step.get_hint(HintLevel::Technique),
+            Hint::Technique(Technique::XWing, _)
+        ));
+    }
+}
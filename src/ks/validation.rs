@@ -0,0 +1,264 @@
+// Copyright 2022 by Daniel Winkelman. All rights reserved.
+
+use std::collections::BTreeSet;
+
+use crate::ks::{
+    combinations::get_combinations,
+    config::SolverConfig,
+    error::SolverError,
+    io::SerializablePuzzle,
+    puzzle::{Backend, Puzzle},
+};
+
+/// A problem found in a serialized puzzle
+#[allow(unused)]
+#[derive(Clone, Debug)]
+pub enum Issue {
+    /// The answer grid does not have one value for each cell
+    WrongNumberOfValues(usize),
+    /// A value in the answer grid is not from 1 to the size of the grid
+    InvalidValue {
+        cell_index: usize,
+        value: usize,
+    },
+    /// A value appears more than once in a row, column, or box of the answer grid
+    RepeatedValueInHouse {
+        cells: Vec<usize>,
+        value: usize,
+    },
+    /// A cell is in no cage or in more than one, or a cage refers to a cell which does not exist
+    CellNotInOneCage {
+        cell_index: usize,
+        num_cages: usize,
+    },
+    /// The values of the answer grid in a cage do not add up to its sum
+    WrongCageSum {
+        cage_index: usize,
+        sum: usize,
+        actual_sum: usize,
+    },
+    /// No set of distinct values from 1 to the size of the grid fills the cage with its sum
+    ImpossibleCageSum {
+        cage_index: usize,
+        sum: usize,
+    },
+    RepeatedValueInCage {
+        cage_index: usize,
+        value: usize,
+    },
+    /// A given does not match the answer grid
+    WrongGiven {
+        cell_index: usize,
+        given: usize,
+    },
+    /// The solver failed before finding all solutions
+    SolverFailed(SolverError),
+    NotUnique {
+        num_solutions: usize,
+    },
+    /// The unique solution differs from the answer grid in these cells
+    WrongSolution {
+        cells: Vec<usize>,
+    },
+}
+
+#[allow(unused)]
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+    /// Whether the solver was run; it is not run if the cages do not cover the board or a cage sum is
+    /// impossible
+    pub solved: bool,
+}
+
+#[allow(unused)]
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.solved && self.issues.is_empty()
+    }
+}
+
+/// Check that the answer grid is a valid sudoku grid that agrees with the cages and the givens, and
+/// that it is the only solution the solver finds
+#[allow(unused)]
+pub fn validate(puzzle: &SerializablePuzzle, config: &SolverConfig) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let shape = puzzle.shape;
+    let values = &puzzle.cell_values;
+    let get_value = |cell_index: &usize| values.get(*cell_index).cloned().unwrap_or(0);
+
+    if values.len() != shape.num_cells() {
+        report.issues.push(Issue::WrongNumberOfValues(values.len()));
+    }
+    for (cell_index, value) in values.iter().enumerate() {
+        if !(1..=shape.size()).contains(value) {
+            report.issues.push(Issue::InvalidValue {
+                cell_index,
+                value: *value,
+            });
+        }
+    }
+    for house in Puzzle::get_houses(&shape) {
+        for value in 1..=shape.size() {
+            if house
+                .cells
+                .iter()
+                .filter(|cell_index| get_value(cell_index) == value)
+                .count()
+                > 1
+            {
+                report.issues.push(Issue::RepeatedValueInHouse {
+                    cells: house.cells.clone(),
+                    value,
+                });
+            }
+        }
+    }
+
+    let mut num_cages = vec![0; shape.num_cells()];
+    let mut cages_cover_board = true;
+    let mut sums_are_possible = true;
+    for (cage_index, cage) in puzzle.cages.iter().enumerate() {
+        for cell_index in cage.cell_indices.iter() {
+            match num_cages.get_mut(*cell_index) {
+                Some(num_cages) => *num_cages += 1,
+                None => {
+                    cages_cover_board = false;
+                    report.issues.push(Issue::CellNotInOneCage {
+                        cell_index: *cell_index,
+                        num_cages: 1,
+                    });
+                }
+            }
+        }
+        let actual_sum = cage.cell_indices.iter().map(get_value).sum::<usize>();
+        if actual_sum != cage.sum {
+            report.issues.push(Issue::WrongCageSum {
+                cage_index,
+                sum: cage.sum,
+                actual_sum,
+            });
+        }
+        if get_combinations(cage.cell_indices.len(), cage.sum, shape.size()).is_err() {
+            sums_are_possible = false;
+            report.issues.push(Issue::ImpossibleCageSum {
+                cage_index,
+                sum: cage.sum,
+            });
+        }
+        let mut cage_values = BTreeSet::new();
+        for value in cage.cell_indices.iter().map(get_value) {
+            if !cage_values.insert(value) {
+                report
+                    .issues
+                    .push(Issue::RepeatedValueInCage { cage_index, value });
+            }
+        }
+    }
+    for (cell_index, num_cages) in num_cages.into_iter().enumerate() {
+        if num_cages != 1 {
+            cages_cover_board = false;
+            report.issues.push(Issue::CellNotInOneCage {
+                cell_index,
+                num_cages,
+            });
+        }
+    }
+
+    for (cell_index, given) in puzzle.givens.iter().flatten().enumerate() {
+        if *given != 0 && *given != get_value(&cell_index) {
+            report.issues.push(Issue::WrongGiven {
+                cell_index,
+                given: *given,
+            });
+        }
+    }
+
+    /* Loading a puzzle whose cages do not cover the board or cannot be filled would fail */
+    if cages_cover_board && sums_are_possible {
+        report.solved = true;
+        let solutions = Puzzle::from_serializable(SerializablePuzzle {
+            cell_values: values.clone(),
+            cages: puzzle.cages.clone(),
+            givens: puzzle.givens.clone(),
+            shape,
+        })
+        .and_then(|solved_puzzle| solved_puzzle.solve_with_limit(Backend::Logic, config, 2));
+        match solutions {
+            Ok(solutions) if solutions.len() == 1 => {
+                let cells = (0..shape.num_cells())
+                    .filter(|cell_index| {
+                        solutions[0].board[*cell_index].get_solution()
+                            != Some(get_value(cell_index))
+                    })
+                    .collect::<Vec<usize>>();
+                if !cells.is_empty() {
+                    report.issues.push(Issue::WrongSolution { cells });
+                }
+            }
+            Ok(solutions) => report.issues.push(Issue::NotUnique {
+                num_solutions: solutions.len(),
+            }),
+            Err(error) => report.issues.push(Issue::SolverFailed(error)),
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load() -> SerializablePuzzle {
+        SerializablePuzzle::from_str(include_str!("test/puzzle_0.json")).unwrap()
+    }
+
+    #[test]
+    fn valid_puzzle() {
+        let report = validate(&load(), &SolverConfig::default());
+        assert!(report.is_valid(), "{:?}", report);
+    }
+
+    #[test]
+    fn stale_values() {
+        /* Swapping two values in a row breaks the columns and the cages, and the solution no
+         * longer matches */
+        let mut puzzle = load();
+        puzzle.cell_values.swap(0, 1);
+        let report = validate(&puzzle, &SolverConfig::default());
+        assert!(report.solved);
+        assert!(report
+            .issues
+            .iter()
+            .any(|issue| matches!(issue, Issue::RepeatedValueInHouse { .. })));
+        assert!(report
+            .issues
+            .iter()
+            .any(|issue| matches!(issue, Issue::WrongSolution { cells } if cells == &[0, 1])));
+
+        /* Without all of the cages, the solver is not run */
+        let mut puzzle = load();
+        puzzle.cages.pop();
+        let report = validate(&puzzle, &SolverConfig::default());
+        assert!(!report.solved);
+        assert!(matches!(
+            report.issues[..],
+            [Issue::CellNotInOneCage { num_cages: 0, .. }, ..]
+        ));
+    }
+
+    #[test]
+    fn impossible_cage_sum() {
+        /* A hand-edited sum is reported rather than handed to the solver */
+        for sum in [0, 1000] {
+            let mut puzzle = load();
+            puzzle.cages[0].sum = sum;
+            let report = validate(&puzzle, &SolverConfig::default());
+            assert!(!report.solved);
+            assert!(report.issues.iter().any(|issue| matches!(
+                issue,
+                Issue::ImpossibleCageSum { cage_index: 0, sum: s } if *s == sum
+            )));
+        }
+    }
+}
@@ -2,15 +2,23 @@
 
 mod ks;
 
-use ks::generator::Generator;
+use ks::{board::Shape, config::SolverConfig, generator::Generator, rating::rate};
 
 fn main() {
-    let mut gen = Generator::new_random();
-    while gen.eliminate_cage() {
+    /* The dimensions of the boxes may be given, such as 2x3 for a 6x6 grid */
+    let shape = match std::env::args().nth(1) {
+        Some(arg) => arg.parse::<Shape>().unwrap(),
+        None => Shape::default(),
+    };
+    let mut gen = Generator::new_random(shape).unwrap();
+    while gen.eliminate_cage().unwrap() {
         gen.serialize().to_json_file("output.json").unwrap();
         println!("{} cages", gen.get_num_cages());
     }
     gen.serialize().to_svg_file("output.svg", "demo").unwrap();
+    if let Ok(rating) = rate(gen.serialize(), &SolverConfig::default()) {
+        println!("{:?} ({})", rating.tier, rating.score);
+    }
 }
 
 #[cfg(test)]
@@ -18,13 +26,13 @@ mod tests {
     macro_rules! solve_from_file {
         ($test_name:ident, $filename:literal) => {
             mod $test_name {
-                use crate::ks::{io::SerializablePuzzle, puzzle::Puzzle};
+                use crate::ks::{config::SolverConfig, io::SerializablePuzzle, puzzle::Puzzle};
                 #[test]
                 fn solve_from_file() {
                     let serialized_puzzle =
                         SerializablePuzzle::from_str(include_str!($filename)).unwrap();
-                    let puzzle = Puzzle::from_serializable(serialized_puzzle);
-                    let solutions = puzzle.solve();
+                    let puzzle = Puzzle::from_serializable(serialized_puzzle).unwrap();
+                    let solutions = puzzle.solve(&SolverConfig::default());
                     assert!(solutions.is_ok());
                     if let Ok(solutions) = solutions {
                         assert_eq!(solutions.len(), 1);